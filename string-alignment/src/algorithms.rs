@@ -1,12 +1,10 @@
-use crate::structs::{Config, Cell, Matrix};
+use crate::structs::{Alignment, AlignmentKind, Config, Cell, EndGaps, Matrix, REAL_MIN};
 //use std::time::SystemTime;
 
 /// Implements Needleman-Wunsch for global alignment
-pub fn needleman_wunsch(s1: &str, s2: &str, config: &Config) {
+pub fn needleman_wunsch(s1: &str, s2: &str, config: &Config) -> Alignment {
     let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, s2.len()+1);
 
-    let real_min = std::i32::MIN - config.h - config.g;
-
     // setup corner
     let mut cur = matrix.index_mut(0, 0);
     cur.d_score = 0;
@@ -16,258 +14,38 @@ pub fn needleman_wunsch(s1: &str, s2: &str, config: &Config) {
     // setup left side
     for i in 1..s1.len()+1 {
         cur = matrix.index_mut(i, 0);
-        cur.d_score = config.h + config.g * i as i32;
-        cur.i_score = real_min;
-        cur.s_score = real_min;
+        cur.d_score = config.d_open(1) + config.g2 * i as i32;
+        cur.i_score = REAL_MIN;
+        cur.s_score = REAL_MIN;
     }
 
     // setup top
     for j in 1..s2.len()+1 {
         cur = matrix.index_mut(0, j);
-        cur.d_score = real_min;
-        cur.i_score = config.h + config.g * j as i32;
-        cur.s_score = real_min;
+        cur.d_score = REAL_MIN;
+        cur.i_score = config.i_open(0) + config.g1 * j as i32;
+        cur.s_score = REAL_MIN;
     }
 
     // fill in the inside
-    let mut d_score: i32;
-    let mut i_score: i32;
-    let mut s_score: i32;
-    let mut cur_d: &Cell;
-    let mut cur_i: &Cell;
-    let mut cur_s: &Cell;
-    let mut new_d_score: i32;
-    let mut new_i_score: i32;
-    let mut new_s_score: i32;
     let mut match_score: i32;
     for i in 1..s1.len()+1 {
         for j in 1..s2.len()+1 {
-
-            // first handle d_score
-            cur_d = matrix.index(i-1, j);
-            d_score = cur_d.d_score + config.g;
-            i_score = cur_d.i_score + config.h + config.g;
-            s_score = cur_d.s_score + config.h + config.g;
-            new_d_score = if d_score > i_score { //choose max d
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
-                }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
-                }
-            };
-
-            // then handle i_score
-            cur_i = matrix.index(i, j-1);
-            d_score = cur_i.d_score + config.h + config.g;
-            i_score = cur_i.i_score + config.g;
-            s_score = cur_i.s_score + config.h + config.g;
-            new_i_score = if d_score > i_score { //choose max i
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
-                }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
-                }
-            };
-
-            // finally handle s_score
-            cur_s = matrix.index(i-1, j-1);
-            d_score = cur_s.d_score;
-            i_score = cur_s.i_score;
-            s_score = cur_s.s_score;
-            match_score = if s1.as_bytes()[i-1] == s2.as_bytes()[j-1] { //subtract 1 because the matrix is offset by 1
-                config.true_match
-            } else {
-                config.mismatch
-            };
-            new_s_score = match_score + if d_score > i_score { //choose max s
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
-                }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
-                }
-            };
-
-            // update the cell
-            cur = matrix.index_mut(i, j);
-            cur.d_score = new_d_score;
-            cur.i_score = new_i_score;
-            cur.s_score = new_s_score;
+            match_score = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]); //subtract 1 because the matrix is offset by 1
+            *matrix.index_mut(i, j) = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), match_score, config, i);
         }
     }
 
-    // start the retrace
-    let mut s1_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut s2_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut ma_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut i: usize = s1.len();
-    let mut j: usize = s2.len();
-    let mut up: i32;
-    let mut left: i32;
-    let mut diag: i32;
-    while i != 0 || j != 0 {
-        up = if i > 0 { //if at edge of matrix
-            matrix.index(i-1, j).score()
-        } else {
-            real_min
-        };
-        left = if j > 0 {
-            matrix.index(i, j-1).score()
-        } else {
-            real_min
-        };
-        diag = if i > 0 && j > 0 {
-            matrix.index(i-1, j-1).score()
-        } else {
-            real_min
-        };
-
-        if up > left && up > diag {
-            s1_str.push(s1.as_bytes()[i-1] as char);
-            s2_str.push('-');
-            ma_str.push(' ');
-            
-            i -= 1;
-        } else if left > up && left > diag {
-            s2_str.push(s2.as_bytes()[j-1] as char);
-            s1_str.push('-');
-            ma_str.push(' ');
-
-            j -= 1;
-        } else { //move diagonally
-            s1_str.push(s1.as_bytes()[i-1] as char);
-            s2_str.push(s2.as_bytes()[j-1] as char);
-            if s1.as_bytes()[i-1] == s2.as_bytes()[j-1] { //if match
-                ma_str.push('|');
-            } else {
-                ma_str.push(' ');
-            }
-
-            i -= 1;
-            j -= 1;
-        }
-    }
-    
-    s1_str = s1_str.chars().rev().collect::<String>();
-    ma_str = ma_str.chars().rev().collect::<String>();
-    s2_str = s2_str.chars().rev().collect::<String>();
-    
-    // s1       1    AC
-    // string2  2
-    let mut s1_header = config.s1_name.clone() + "  ";
-    let mut s2_header = config.s2_name.clone() + "  ";
-
-    // pad the shorter string
-    if s1_header.len() > s2_header.len() {
-        s2_header += &" ".repeat(s1_header.len() - s2_header.len());
-    } else {
-        s1_header += &" ".repeat(s2_header.len() - s1_header.len());
-    }
-
-    let ma_header = " ".repeat(s1_header.len());
-
-    let max_num_len: usize = 2 + if s1.len() > s2.len() {s1.len().to_string().len()} else {s2.len().to_string().len()};
-    let mut s1_num_len: usize; //length of the number, for padding reasons
-    let mut s2_num_len: usize; //length of the number, for padding reasons
-    let mut s1_chunk: &str; //60 chars
-    let mut s2_chunk: &str;
-    let mut ma_chunk: &str;
-    let mut s1_counter = 0;
-    let mut s2_counter = 0;
-
-    // print the retrace
-    let mut s1_counter_next: usize;
-    let mut s2_counter_next: usize;
-    for i in 0..s1_str.len()/60 {
-                                              
-        s1_chunk = &s1_str[i*60..(i+1)*60]; //the chunk we're printing now
-        s2_chunk = &s2_str[i*60..(i+1)*60];
-        ma_chunk = &ma_str[i*60..(i+1)*60];
-
-        s1_num_len = (s1_counter+1).to_string().len(); //stringify the current location
-        s2_num_len = (s2_counter+1).to_string().len();
-        
-        s1_counter_next = s1_counter + 60 - s1_chunk.matches('-').count(); //calculate the new location
-        s2_counter_next = s2_counter + 60 - s2_chunk.matches('-').count(); //calculate the new location
-
-        println!("{}{}{}{}  {}", s1_header, s1_counter+1, " ".repeat(max_num_len-s1_num_len), s1_chunk, s1_counter_next);
-        println!("{}{}{}", ma_header, " ".repeat(max_num_len), ma_chunk);
-        println!("{}{}{}{}  {}", s2_header, s2_counter+1, " ".repeat(max_num_len-s2_num_len), s2_chunk, s2_counter_next);
-        println!();
-
-        s1_counter = s1_counter_next; //update the current location
-        s2_counter = s2_counter_next;
-    }
-    let leftover = s1_str.len() % 60;
-    if leftover > 0 { //have some left over
-        s1_chunk = &s1_str[s1_str.len()-leftover..]; //the chunk we're printing now
-        s2_chunk = &s2_str[s1_str.len()-leftover..];
-        ma_chunk = &ma_str[s1_str.len()-leftover..];
-
-        s1_num_len = (s1_counter+1).to_string().len(); //stringify the current location
-        s2_num_len = (s2_counter+1).to_string().len();
-
-        println!("{}{}{}{}  {}", s1_header, s1_counter+1, " ".repeat(max_num_len-s1_num_len), s1_chunk, s1.len());
-        println!("{}{}{}", ma_header, " ".repeat(max_num_len), ma_chunk);
-        println!("{}{}{}{}  {}", s2_header, s2_counter+1, " ".repeat(max_num_len-s2_num_len), s2_chunk, s2.len());
-    }
-
-    let mut matches = 0;
-    let mut mismatches = 0;
-    let mut in_gap = false;
-    let mut gap_start = 0;
-    let mut gap_extension = 0;
-    
-    // calculate matches, mismatches, gap openings and gap extensions
-    for i in 0..s1_str.len() {
-        if ma_str.as_bytes()[i] == '|' as u8 { //found a match
-            in_gap = false;
-            matches += 1;
-        } else { //not a match
-            if s1_str.as_bytes()[i] == '-' as u8 || s2_str.as_bytes()[i] == '-' as u8 { //encountered a gap
-                if !in_gap {
-                    in_gap = true;
-                    gap_start += 1;
-                    gap_extension += 1;
-                } else {
-                    gap_extension += 1;
-                }
-            } else { //not a match or a gap, ie a mismatch
-                in_gap = false;
-                mismatches += 1;
-            }
-        }
-    }
+    // start the retrace from the bottom right corner
+    let (s1_str, s2_str, _, _) = retrace(|i, j| matrix.index(i, j), s1, s2, config, s1.len(), s2.len(), false);
 
-    println!("\n\n");
-    println!("Report:\n");
-    println!("Global optimal score = {}\n", matrix.index(s1.len(), s2.len()).score());
-    println!("Number of:  matches = {}, mismatches = {}, opening gaps = {}, gap extensions = {}\n", matches, mismatches, gap_start, gap_extension);
-    println!("Identities = {}/{} ({}%), Gaps = {}/{} ({}%)",
-        matches, s1_str.len(), (Into::<f64>::into(matches) / s1_str.len() as f64 * 100.0) as i32,
-        gap_extension, s1_str.len(), (Into::<f64>::into(gap_extension) / s1_str.len() as f64 * 100.0) as i32);
+    let score = matrix.index(s1.len(), s2.len()).score();
+    Alignment::from_rows(AlignmentKind::Global, s1_str, s2_str, 0, 0, score, config)
 }
 
 
 /// Implements Smith-Waterman for local alignment
-pub fn smith_waterman(s1: &str, s2: &str, config: &Config) {
+pub fn smith_waterman(s1: &str, s2: &str, config: &Config) -> Alignment {
     let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, s2.len()+1);
 
     // setup corner
@@ -293,256 +71,389 @@ pub fn smith_waterman(s1: &str, s2: &str, config: &Config) {
     }
 
     // fill in the inside
-    let mut d_score: i32;
-    let mut i_score: i32;
-    let mut s_score: i32;
-    let mut cur_d: &Cell;
-    let mut cur_i: &Cell;
-    let mut cur_s: &Cell;
-    let mut new_d_score: i32;
-    let mut new_i_score: i32;
-    let mut new_s_score: i32;
     let mut match_score: i32;
     let mut top_i: usize = s1.len();
     let mut top_j: usize = s2.len();
     let mut top_score: i32 = 0;
     for i in 1..s1.len()+1 {
         for j in 1..s2.len()+1 {
+            match_score = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+            let mut new_cell = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), match_score, config, i);
+            new_cell.mask_seed(matrix.index(i-1, j-1), s1.as_bytes()[i-1], s2.as_bytes()[j-1], config);
+            new_cell.clamp(); //fix all negative scores
 
-            // first handle d_score
-            cur_d = matrix.index(i-1, j);
-            d_score = cur_d.d_score + config.g;
-            i_score = cur_d.i_score + config.h + config.g;
-            s_score = cur_d.s_score + config.h + config.g;
-            new_d_score = if d_score > i_score { //choose max d
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
-                }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
-                }
-            };
+            // check to see if this cell is the highest scoring
+            if new_cell.score() > top_score {
+                top_score = new_cell.score();
+                top_i = i;
+                top_j = j;
+            }
+            *matrix.index_mut(i, j) = new_cell;
+        }
+    }
 
-            // then handle i_score
-            cur_i = matrix.index(i, j-1);
-            d_score = cur_i.d_score + config.h + config.g;
-            i_score = cur_i.i_score + config.g;
-            s_score = cur_i.s_score + config.h + config.g;
-            new_i_score = if d_score > i_score { //choose max i
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
+    // start the retrace from the highest scoring cell
+    let (s1_str, s2_str, i_0, j_0) = retrace(|i, j| matrix.index(i, j), s1, s2, config, top_i, top_j, true);
+
+    let score = matrix.index(top_i, top_j).score();
+    Alignment::from_rows(AlignmentKind::Local, s1_str, s2_str, i_0, j_0, score, config)
+}
+
+
+
+/// Finds up to k local alignments scoring at least min_score that never align the same pair of characters (Waterman-Eggert).
+/// After each hit its pairs are forbidden, and rows are filled again from where it starts until they stop changing.
+pub fn waterman_eggert(s1: &str, s2: &str, config: &Config, k: usize, min_score: i32) -> Vec<Alignment> {
+    let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, s2.len()+1); //the edges stay at 0
+    let mut forbidden: Matrix<bool> = Matrix::with_shape(s1.len()+1, s2.len()+1);
+    let mut alignments: Vec<Alignment> = Vec::new();
+
+    let mut first_row = 1;
+    let mut last_row = s1.len(); //rows after this one are only refilled while their neighbours keep changing
+    while alignments.len() < k {
+        for i in first_row..s1.len()+1 {
+            let mut changed = false;
+            for j in 1..s2.len()+1 {
+                let match_score = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+                let mut new_cell = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), match_score, config, i);
+                new_cell.mask_seed(matrix.index(i-1, j-1), s1.as_bytes()[i-1], s2.as_bytes()[j-1], config);
+                if *forbidden.index(i, j) { //an earlier hit already used this pair
+                    new_cell.s_score = 0;
                 }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
+                new_cell.clamp();
+                if *matrix.index(i, j) != new_cell {
+                    changed = true;
+                    *matrix.index_mut(i, j) = new_cell;
                 }
-            };
+            }
+            if !changed && i > last_row {
+                break;
+            }
+        }
 
-            // finally handle s_score
-            cur_s = matrix.index(i-1, j-1);
-            d_score = cur_s.d_score;
-            i_score = cur_s.i_score;
-            s_score = cur_s.s_score;
-            match_score = if s1.as_bytes()[i-1] == s2.as_bytes()[j-1] {
-                config.true_match
-            } else {
-                config.mismatch
-            };
-            new_s_score = match_score + if d_score > i_score { //choose max s
-                if d_score > s_score {
-                    d_score
-                } else {
-                    s_score    
-                }
-            } else {
-                if i_score > s_score {
-                    i_score
-                } else {
-                    s_score
+        // the next hit ends at the best remaining cell
+        let mut top_i: usize = 0;
+        let mut top_j: usize = 0;
+        let mut top_score: i32 = 0;
+        for i in 1..s1.len()+1 {
+            for j in 1..s2.len()+1 {
+                if matrix.index(i, j).score() > top_score {
+                    top_score = matrix.index(i, j).score();
+                    top_i = i;
+                    top_j = j;
                 }
-            };
+            }
+        }
+        if top_score == 0 || top_score < min_score {
+            break;
+        }
 
-            // update the cell, fixing all negative scores
-            cur = matrix.index_mut(i, j);
-            if new_d_score < 0 { //d score
-                cur.d_score = 0;
-            } else {
-                cur.d_score = new_d_score;
+        let (s1_str, s2_str, i_0, j_0) = retrace(|i, j| matrix.index(i, j), s1, s2, config, top_i, top_j, true);
+        let alignment = Alignment::from_rows(AlignmentKind::Local, s1_str, s2_str, i_0, j_0, top_score, config);
+
+        // forbid every pair the hit aligned
+        let (mut i, mut j) = (i_0, j_0);
+        for (&c1, &c2) in alignment.s1_row.as_bytes().iter().zip(alignment.s2_row.as_bytes()) {
+            if c1 != b'-' && c2 != b'-' {
+                *forbidden.index_mut(i+1, j+1) = true;
             }
-            if new_i_score < 0 { //i score
-                cur.i_score = 0;
-            } else {
-                cur.i_score = new_i_score;
+            i += (c1 != b'-') as usize;
+            j += (c2 != b'-') as usize;
+        }
+        first_row = alignment.s1_start + 1;
+        last_row = alignment.s1_end;
+        alignments.push(alignment);
+    }
+    alignments
+}
+
+
+
+/// Implements end-gap-free alignment, where gaps at the chosen ends of either string cost nothing.
+/// Covers semi-global, overlap and glocal alignment depending on which ends are free.
+pub fn end_gap_free(s1: &str, s2: &str, config: &Config, ends: &EndGaps, kind: AlignmentKind) -> Alignment {
+    let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, s2.len()+1);
+
+    // setup corner
+    *matrix.index_mut(0, 0) = Cell::new();
+
+    // setup left side, where s1's leading characters go unaligned
+    for i in 1..s1.len()+1 {
+        *matrix.index_mut(i, 0) = Cell {
+            d_score: if ends.s1_start {0} else {config.d_open(1) + config.g2 * i as i32},
+            i_score: REAL_MIN,
+            s_score: REAL_MIN,
+        };
+    }
+
+    // setup top, where s2's leading characters go unaligned
+    for j in 1..s2.len()+1 {
+        *matrix.index_mut(0, j) = Cell {
+            d_score: REAL_MIN,
+            i_score: if ends.s2_start {0} else {config.i_open(0) + config.g1 * j as i32},
+            s_score: REAL_MIN,
+        };
+    }
+
+    // fill in the inside
+    let mut match_score: i32;
+    for i in 1..s1.len()+1 {
+        for j in 1..s2.len()+1 {
+            match_score = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+            *matrix.index_mut(i, j) = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), match_score, config, i);
+        }
+    }
+
+    // the alignment may end anywhere along the last column if s1's tail is free, or the last row if s2's is
+    let mut end_i = s1.len();
+    let mut end_j = s2.len();
+    let mut end_score = matrix.index(end_i, end_j).score();
+    if ends.s1_end {
+        for i in 0..s1.len() {
+            if matrix.index(i, s2.len()).score() > end_score {
+                end_score = matrix.index(i, s2.len()).score();
+                end_i = i;
+                end_j = s2.len();
             }
-            if new_s_score < 0 { //s score
-                cur.s_score = 0;
-            } else {
-                cur.s_score = new_s_score;
+        }
+    }
+    if ends.s2_end {
+        for j in 0..s2.len() {
+            if matrix.index(s1.len(), j).score() > end_score {
+                end_score = matrix.index(s1.len(), j).score();
+                end_i = s1.len();
+                end_j = j;
             }
+        }
+    }
 
-            // check to see if this cell is the highest scoring
-            if cur.score() > top_score {
-                top_score = cur.score();
-                top_i = i;
-                top_j = j;
-            }
+    // retrace to the corner, then drop the free leading gap along the edge
+    let (mut s1_str, mut s2_str, _, _) = retrace(|i, j| matrix.index(i, j), s1, s2, config, end_i, end_j, false);
+    let mut s1_start = 0;
+    let mut s2_start = 0;
+    if ends.s1_start {
+        s1_start = s2_str.len() - s2_str.trim_start_matches('-').len();
+    }
+    if ends.s2_start && s1_start == 0 {
+        s2_start = s1_str.len() - s1_str.trim_start_matches('-').len();
+    }
+    s1_str.drain(..s1_start+s2_start);
+    s2_str.drain(..s1_start+s2_start);
+
+    Alignment::from_rows(kind, s1_str, s2_str, s1_start, s2_start, end_score, config)
+}
+
+
+/// Computes only the optimal global (or local) score, keeping two rows as long as the shorter string
+pub fn optimal_score(s1: &str, s2: &str, config: &Config, local: bool) -> i32 {
+    // the shorter string runs along the rows, swapping back when looking up scores, unless a gap track pins s1 to the rows
+    let swapped = s2.len() > s1.len() && config.gap_track.is_none();
+    let swapped_config;
+    let config = if swapped {
+        swapped_config = config.swapped();
+        &swapped_config
+    } else {
+        config
+    };
+    let (outer, inner) = if swapped {
+        (s2.as_bytes(), s1.as_bytes())
+    } else {
+        (s1.as_bytes(), s2.as_bytes())
+    };
+
+    let mut prev: Vec<Cell> = vec![Cell::new(); inner.len()+1];
+    let mut cur: Vec<Cell> = vec![Cell::new(); inner.len()+1];
+
+    // setup top
+    if !local {
+        for (j, cell) in prev.iter_mut().enumerate().skip(1) {
+            cell.d_score = REAL_MIN;
+            cell.i_score = config.i_open(0) + config.g1 * j as i32;
+            cell.s_score = REAL_MIN;
         }
     }
 
-    // start the retrace
-    let mut s1_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut s2_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut ma_str: String = String::with_capacity(s1.len() + s2.len());
-    let mut i: usize = top_i;
-    let mut j: usize = top_j;
-
-    let mut up: i32;
-    let mut left: i32;
-    let mut diag: i32;
-    while matrix.index(i, j).score() != 0 {
-        up = matrix.index(i-1, j).score();
-        left = matrix.index(i, j-1).score();
-        diag = matrix.index(i-1, j-1).score();
-        if up > left && up > diag {
-            s1_str.push(s1.as_bytes()[i-1] as char);
-            s2_str.push('-');
-            ma_str.push(' ');
-            
-            i -= 1;
-        } else if left > up && left > diag {
-            s2_str.push(s2.as_bytes()[j-1] as char);
-            s1_str.push('-');
-            ma_str.push(' ');
-
-            j -= 1;
-        } else { //move diagonally
-            s1_str.push(s1.as_bytes()[i-1] as char);
-            s2_str.push(s2.as_bytes()[j-1] as char);
-            if s1.as_bytes()[i-1] == s2.as_bytes()[j-1] { //if match
-                ma_str.push('|');
+    let mut top_score: i32 = 0;
+    for i in 1..outer.len()+1 {
+        // setup left side
+        cur[0] = if local {
+            Cell::new()
+        } else {
+            Cell { d_score: config.d_open(1) + config.g2 * i as i32, i_score: REAL_MIN, s_score: REAL_MIN }
+        };
+
+        // fill in the row
+        for j in 1..inner.len()+1 {
+            let match_score = if swapped {
+                config.substitution(inner[j-1], outer[i-1])
             } else {
-                ma_str.push(' ');
+                config.substitution(outer[i-1], inner[j-1])
+            };
+            let mut new_cell = Cell::next(&prev[j], &cur[j-1], &prev[j-1], match_score, config, i);
+            if local {
+                new_cell.mask_seed(&prev[j-1], outer[i-1], inner[j-1], config);
+                new_cell.clamp();
+                top_score = top_score.max(new_cell.score());
             }
-
-            i -= 1;
-            j -= 1;
+            cur[j] = new_cell;
         }
+        std::mem::swap(&mut prev, &mut cur);
     }
-    let i_0 = i; //coordinates of the 0,0
-    let j_0 = j;
-    
-    // print the output
-    s1_str = s1_str.chars().rev().collect::<String>();
-    ma_str = ma_str.chars().rev().collect::<String>();
-    s2_str = s2_str.chars().rev().collect::<String>();
-    
-    // s1       1    AC
-    // string2  2
-    // establish the headers here (the names of the string sequences)
-    let mut s1_header = config.s1_name.clone() + "  ";
-    let mut s2_header = config.s2_name.clone() + "  ";
-
-    // pad the shorter header
-    if s1_header.len() > s2_header.len() {
-        s2_header += &" ".repeat(s1_header.len() - s2_header.len());
+
+    if local {
+        top_score
     } else {
-        s1_header += &" ".repeat(s2_header.len() - s1_header.len());
+        prev[inner.len()].score()
     }
+}
 
-    let ma_header = " ".repeat(s1_header.len());
 
-    let max_num_len: usize = 2 + if s1.len() > s2.len() {s1.len().to_string().len()} else {s2.len().to_string().len()};
-    let mut s1_num_len: usize; //length of the number, for padding reasons
-    let mut s2_num_len: usize; //length of the number, for padding reasons
-    let mut s1_chunk: &str; //60 chars
-    let mut s2_chunk: &str;
-    let mut ma_chunk: &str;
-    let mut s1_counter = i_0;
-    let mut s2_counter = j_0;
-    
-    // print the retrace
-    let mut s1_counter_next: usize;
-    let mut s2_counter_next: usize;
-    for i in 0..s1_str.len()/60 {
-                                              
-        s1_chunk = &s1_str[i*60..(i+1)*60]; //the chunk we're printing now
-        s2_chunk = &s2_str[i*60..(i+1)*60];
-        ma_chunk = &ma_str[i*60..(i+1)*60];
-
-        s1_num_len = (s1_counter+1).to_string().len(); //stringify the current location
-        s2_num_len = (s2_counter+1).to_string().len();
-        
-        s1_counter_next = s1_counter + 60 - s1_chunk.matches('-').count(); //calculate the new location
-        s2_counter_next = s2_counter + 60 - s2_chunk.matches('-').count();
-
-        println!("{}{}{}{}  {}", s1_header, s1_counter+1, " ".repeat(max_num_len-s1_num_len), s1_chunk, s1_counter_next);
-        println!("{}{}{}", ma_header, " ".repeat(max_num_len), ma_chunk);
-        println!("{}{}{}{}  {}", s2_header, s2_counter+1, " ".repeat(max_num_len-s2_num_len), s2_chunk, s2_counter_next);
-        println!();
-
-        s1_counter = s1_counter_next; //update the current location
-        s2_counter = s2_counter_next;
+/// Which of a cell's three scores a path goes through, and so which step it takes into the cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    D, //s1 against a gap
+    I, //s2 against a gap
+    S, //a pair of characters
+}
+
+
+/// Picks the best state of a cell, preferring S, then D, then I on ties
+pub fn best_state(cell: &Cell) -> State {
+    if cell.s_score >= cell.d_score && cell.s_score >= cell.i_score {
+        State::S
+    } else if cell.d_score >= cell.i_score {
+        State::D
+    } else {
+        State::I
     }
-    let leftover = s1_str.len() % 60;
-    if leftover > 0 { //have some left over
-        s1_chunk = &s1_str[s1_str.len()-leftover..]; //the chunk we're printing now
-        s2_chunk = &s2_str[s1_str.len()-leftover..];
-        ma_chunk = &ma_str[s1_str.len()-leftover..];
-
-        s1_num_len = (s1_counter+1).to_string().len(); //stringify the current location
-        s2_num_len = (s2_counter+1).to_string().len();
-
-        println!("{}{}{}{}  {}", s1_header, s1_counter+1, " ".repeat(max_num_len-s1_num_len), s1_chunk, s1_counter+leftover);
-        println!("{}{}{}", ma_header, " ".repeat(max_num_len), ma_chunk);
-        println!("{}{}{}{}  {}", s2_header, s2_counter+1, " ".repeat(max_num_len-s2_num_len), s2_chunk, s2_counter+leftover);
+}
+
+
+/// Follows the filled matrix back from (i, j), returning the two gapped rows and where they begin.
+/// Local retraces stop where the alignment started from an empty prefix, global ones at the corner.
+/// Each step follows the state its score came from, not the neighbour with the best total, so the rows always score what
+/// the matrix says. Among equally good steps, S wins, then D, then I.
+pub fn retrace<'a>(cell: impl Fn(usize, usize) -> &'a Cell, s1: &str, s2: &str, config: &Config, i: usize, j: usize, local: bool) -> (String, String, usize, usize) {
+    let (path, i_0, j_0) = retrace_path(cell, config, i, j, local);
+    let s1_bytes = s1.as_bytes();
+    let s2_bytes = s2.as_bytes();
+    let mut s1_str: String = String::with_capacity(path.len());
+    let mut s2_str: String = String::with_capacity(path.len());
+    let (mut i, mut j) = (i_0, j_0);
+    for state in path {
+        match state {
+            State::S => {
+                s1_str.push(s1_bytes[i] as char);
+                s2_str.push(s2_bytes[j] as char);
+                i += 1;
+                j += 1;
+            },
+            State::D => {
+                s1_str.push(s1_bytes[i] as char);
+                s2_str.push('-');
+                i += 1;
+            },
+            State::I => {
+                s1_str.push('-');
+                s2_str.push(s2_bytes[j] as char);
+                j += 1;
+            },
+        }
     }
+    (s1_str, s2_str, i_0, j_0)
+}
 
-    let mut matches = 0;
-    let mut mismatches = 0;
-    let mut in_gap = false;
-    let mut gap_start = 0;
-    let mut gap_extension = 0;
-
-    // calculate matches, mismatches, gap openings and gap extensions
-    for i in 0..s1_str.len() {
-        if ma_str.as_bytes()[i] == '|' as u8 { //found a match
-            in_gap = false;
-            matches += 1;
-        } else { //not a match
-            if s1_str.as_bytes()[i] == '-' as u8 || s2_str.as_bytes()[i] == '-' as u8 { //encountered a gap
-                if !in_gap {
-                    in_gap = true;
-                    gap_start += 1;
-                    gap_extension += 1;
-                } else {
-                    gap_extension += 1;
+
+/// Follows the filled matrix back from (i, j), returning the steps of the path in order and where it begins
+pub fn retrace_path<'a>(cell: impl Fn(usize, usize) -> &'a Cell, config: &Config, mut i: usize, mut j: usize, local: bool) -> (Vec<State>, usize, usize) {
+    let mut path: Vec<State> = Vec::with_capacity(i + j);
+    let mut state = best_state(cell(i, j));
+
+    while i != 0 || j != 0 {
+        let cur = cell(i, j);
+        if local && (i == 0 || j == 0 || cur.score() == 0) { //a local alignment can't extend past an empty prefix
+            break;
+        }
+        if j == 0 { //along the edges there's only one way back
+            state = State::D;
+        } else if i == 0 {
+            state = State::I;
+        }
+        path.push(state);
+        match state {
+            State::S => { //move diagonally
+                let prev = cell(i-1, j-1);
+                i -= 1;
+                j -= 1;
+                if local && prev.score() == 0 { //this pair started the alignment
+                    break;
                 }
-            } else { //not a match or a gap, ie a mismatch
-                in_gap = false;
-                mismatches += 1;
-            }
+                state = best_state(prev);
+            },
+            State::D => { //move up, s2 has a gap
+                let prev = cell(i-1, j);
+                state = if prev.d_score + config.g2 == cur.d_score {
+                    State::D
+                } else if prev.s_score + config.d_open(i) + config.g2 == cur.d_score {
+                    State::S
+                } else {
+                    State::I
+                };
+                i -= 1;
+            },
+            State::I => { //move left, s1 has a gap
+                let prev = cell(i, j-1);
+                state = if prev.i_score + config.g1 == cur.i_score {
+                    State::I
+                } else if prev.s_score + config.i_open(i) + config.g1 == cur.i_score {
+                    State::S
+                } else {
+                    State::D
+                };
+                j -= 1;
+            },
         }
     }
 
-    println!("\n\n");
-    println!("Report:\n");
-    println!("Local optimal score = {}\n", matrix.index(top_i, top_j).score());
-    println!("Number of:  matches = {}, mismatches = {}, opening gaps = {}, gap extensions = {}\n", matches, mismatches, gap_start, gap_extension);
-    println!("Identities = {}/{} ({}%), Gaps = {}/{} ({}%)",
-        matches, s1_str.len(), (Into::<f64>::into(matches) / s1_str.len() as f64 * 100.0) as i32,
-        gap_extension, s1_str.len(), (Into::<f64>::into(gap_extension) / s1_str.len() as f64 * 100.0) as i32);
+    path.reverse();
+    (path, i, j)
 }
 
 
+/// Scores a pair of gapped rows of a global alignment under the affine gap model
+pub fn score_rows(s1_row: &str, s2_row: &str, config: &Config) -> i32 {
+    let mut score = 0;
+    let mut last_gap: u8 = 0; //0: not in a gap, 1: gap in s1, 2: gap in s2
+    let mut i = 0; //characters of s1 so far
+    for (&c1, &c2) in s1_row.as_bytes().iter().zip(s2_row.as_bytes()) {
+        let this_gap = if c1 == b'-' {
+            1
+        } else if c2 == b'-' {
+            2
+        } else {
+            0
+        };
+        match this_gap {
+            0 => score += config.substitution(c1, c2),
+            1 => {
+                if this_gap != last_gap {
+                    score += config.i_open(i);
+                }
+                score += config.g1;
+            },
+            _ => {
+                if this_gap != last_gap {
+                    score += config.d_open(i + 1);
+                }
+                score += config.g2;
+            },
+        }
+        i += (c1 != b'-') as usize;
+        last_gap = this_gap;
+    }
+    score
+}
+
 
 // OUTPUT:
 // ********
@@ -580,3 +491,131 @@ pub fn smith_waterman(s1: &str, s2: &str, config: &Config) {
 */
 
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::random::Rng;
+    use crate::testing::{config, random_dna};
+
+    /// Pairs of positions an alignment lines up
+    fn aligned_pairs(alignment: &Alignment) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let (mut i, mut j) = (alignment.s1_start, alignment.s2_start);
+        for (&c1, &c2) in alignment.s1_row.as_bytes().iter().zip(alignment.s2_row.as_bytes()) {
+            if c1 != b'-' && c2 != b'-' {
+                pairs.push((i, j));
+            }
+            i += (c1 != b'-') as usize;
+            j += (c2 != b'-') as usize;
+        }
+        pairs
+    }
+
+    #[test]
+    fn retrace_rows_score_what_the_matrix_says() {
+        // stepping to the neighbour with the best total gave T- against AT, which scores -8 rather than -5
+        let defaults = config("");
+        let alignment = needleman_wunsch("T", "AT", &defaults);
+        assert_eq!((alignment.s1_row.as_str(), alignment.s2_row.as_str(), alignment.score), ("-T", "AT", -5));
+        let alignment = needleman_wunsch("TG", "TTG", &defaults);
+        assert_eq!((alignment.s1_row.as_str(), alignment.s2_row.as_str(), alignment.score), ("-TG", "TTG", -4));
+
+        let mut rng = Rng::new(15);
+        for text in ["", "match = 2\nmismatch = -3\nh1 = -5\ng1 = 0\nh2 = -4\ng2 = -1\n"] {
+            let config = config(text);
+            for _ in 0..300 {
+                let s1 = random_dna(&mut rng, 20);
+                let s2 = random_dna(&mut rng, 20);
+                let global = needleman_wunsch(&s1, &s2, &config);
+                assert_eq!(score_rows(&global.s1_row, &global.s2_row, &config), global.score, "{} vs {}", s1, s2);
+                let local = smith_waterman(&s1, &s2, &config);
+                assert_eq!(score_rows(&local.s1_row, &local.s2_row, &config), local.score, "{} vs {}", s1, s2);
+            }
+        }
+    }
+
+    #[test]
+    fn optimal_score_matches_full_matrix() {
+        let mut rng = Rng::new(13);
+        for text in ["match = 1\nmismatch = -2\nh = -5\ng = -1\n", "match = 2\nmismatch = -3\nh1 = -5\ng1 = 0\nh2 = -4\ng2 = -1\n"] {
+            let mut config = config(text);
+            for round in 0..400 {
+                let s1 = random_dna(&mut rng, 30);
+                let s2 = random_dna(&mut rng, 30);
+                config.gap_track = (round % 4 == 3).then(|| (0..s1.len().max(1)).map(|_| rng.below(5) as f64 / 2.0).collect());
+                assert_eq!(optimal_score(&s1, &s2, &config, false), needleman_wunsch(&s1, &s2, &config).score, "{} vs {}", s1, s2);
+                assert_eq!(optimal_score(&s1, &s2, &config, true), smith_waterman(&s1, &s2, &config).score, "{} vs {}", s1, s2);
+            }
+        }
+    }
+
+    #[test]
+    fn waterman_eggert_hits_are_disjoint() {
+        let config = config("match = 2\nmismatch = -3\nh1 = -5\ng1 = -1\nh2 = -4\ng2 = -2\n");
+        let mut rng = Rng::new(14);
+        for _ in 0..200 {
+            let s1 = random_dna(&mut rng, 40);
+            let s2 = random_dna(&mut rng, 40);
+            let hits = waterman_eggert(&s1, &s2, &config, 6, 3);
+            let best = smith_waterman(&s1, &s2, &config);
+            if best.score < 3 {
+                assert!(hits.is_empty());
+                continue;
+            }
+            assert_eq!((hits[0].score, &hits[0].s1_row, &hits[0].s2_row, hits[0].s1_start, hits[0].s2_start),
+                (best.score, &best.s1_row, &best.s2_row, best.s1_start, best.s2_start));
+
+            let mut used: HashSet<(usize, usize)> = HashSet::new();
+            for (k, hit) in hits.iter().enumerate() {
+                assert!(hit.score >= 3);
+                assert!(k == 0 || hit.score <= hits[k-1].score);
+                assert_eq!(score_rows(&hit.s1_row, &hit.s2_row, &config), hit.score);
+                for pair in aligned_pairs(hit) {
+                    assert!(used.insert(pair), "hit {} of {} vs {} realigns {:?}", k+1, s1, s2, pair);
+                }
+            }
+        }
+    }
+
+    /// Rows, score and coordinates of an alignment, for comparing against known answers
+    fn summary(alignment: &Alignment) -> (&str, &str, i32, usize, usize, usize, usize) {
+        (&alignment.s1_row, &alignment.s2_row, alignment.score, alignment.s1_start, alignment.s1_end, alignment.s2_start, alignment.s2_end)
+    }
+
+    #[test]
+    fn end_gap_free_known_answers() {
+        let config = config("match = 1\nmismatch = -1\nh = -2\ng = -1\n");
+
+        // free leading gap on s1 and free trailing gap on s2, then the other way round
+        let alignment = end_gap_free("TTACGT", "ACGTCC", &config, &EndGaps::semi_global(), AlignmentKind::SemiGlobal);
+        assert_eq!(summary(&alignment), ("ACGT", "ACGT", 4, 2, 6, 0, 4));
+        let alignment = end_gap_free("ACGTCC", "TTACGT", &config, &EndGaps::semi_global(), AlignmentKind::SemiGlobal);
+        assert_eq!(summary(&alignment), ("ACGT", "ACGT", 4, 0, 4, 2, 6));
+
+        // a suffix of s1 against a prefix of s2, but not the other way round
+        let alignment = end_gap_free("GGGACGTA", "ACGTATT", &config, &EndGaps::overlap(), AlignmentKind::Overlap);
+        assert_eq!(summary(&alignment), ("ACGTA", "ACGTA", 5, 3, 8, 0, 5));
+        let alignment = end_gap_free("ACGTATT", "GGGACGTA", &config, &EndGaps::overlap(), AlignmentKind::Overlap);
+        assert!(alignment.score < 5);
+
+        // all of s2 lies inside s1, mismatched ends included
+        let alignment = end_gap_free("GGACCTGG", "TACCTA", &config, &EndGaps::glocal(), AlignmentKind::Glocal);
+        assert_eq!(summary(&alignment), ("GACCTG", "TACCTA", 2, 1, 7, 0, 6));
+        assert_eq!(alignment.kind, AlignmentKind::Glocal);
+    }
+
+    #[test]
+    fn all_free_end_gaps_on_identical_strings_match_needleman_wunsch() {
+        let config = config("match = 2\nmismatch = -3\nh1 = -5\ng1 = -1\nh2 = -4\ng2 = -2\n");
+        let mut rng = Rng::new(16);
+        for _ in 0..100 {
+            let s = random_dna(&mut rng, 30);
+            let global = needleman_wunsch(&s, &s, &config);
+            let free = end_gap_free(&s, &s, &config, &EndGaps::semi_global(), AlignmentKind::SemiGlobal);
+            assert_eq!(summary(&free), summary(&global), "{}", s);
+        }
+    }
+}
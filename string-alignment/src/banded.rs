@@ -0,0 +1,230 @@
+use crate::algorithms::retrace;
+use crate::structs::{Alignment, AlignmentKind, Cell, Config, Matrix, REAL_MIN};
+
+
+/// How a banded alignment turned out
+#[derive(Debug, Clone, Copy)]
+pub struct BandReport {
+    pub band: usize, //how many diagonals either side of the main ones were filled
+    pub touched_edge: bool, //whether the optimal path ran along the edge of the band
+    pub optimal: bool, //whether the alignment is provably as good as the unbanded one
+}
+
+
+/// Aligns only the cells within `band` diagonals of the main diagonal.
+/// With `widen`, the band is doubled and the alignment retried until it's provably optimal, meaning nothing that leaves
+/// the band could score better or the band covers the whole matrix.
+pub fn banded(s1: &str, s2: &str, config: &Config, local: bool, band: usize, widen: bool) -> (Alignment, BandReport) {
+    let mut band = band;
+    loop {
+        let (alignment, report) = banded_once(s1, s2, config, local, band);
+        if !widen || report.optimal {
+            return (alignment, report);
+        }
+        band = band.max(1) * 2;
+    }
+}
+
+
+/// Fills and retraces a single band
+fn banded_once(s1: &str, s2: &str, config: &Config, local: bool, band: usize) -> (Alignment, BandReport) {
+    let n = s1.len() as isize;
+    let m = s2.len() as isize;
+
+    // the band spans from the diagonal through (0,0) to the one through (n,m), plus `band` on either side
+    let shift = m - n;
+    let low = (shift.min(0) - band as isize).max(-n); //lowest j-i offset kept
+    let high = (shift.max(0) + band as isize).min(m); //highest j-i offset kept
+    let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, (high - low + 1) as usize);
+
+    // cells outside the band can't be reached in a global alignment, and are just empty in a local one
+    let outside = if local {
+        Cell::new()
+    } else {
+        Cell { d_score: REAL_MIN, i_score: REAL_MIN, s_score: REAL_MIN }
+    };
+    let in_band = |i: usize, j: usize| {
+        let offset = j as isize - i as isize;
+        offset >= low && offset <= high
+    };
+    let column = |i: usize, j: usize| (j as isize - i as isize - low) as usize;
+
+    // fill in every cell of the band
+    let mut top_i: usize = s1.len();
+    let mut top_j: usize = s2.len();
+    let mut top_score: i32 = 0;
+    for i in 0..s1.len()+1 {
+        let first = (i as isize + low).max(0) as usize;
+        let last = (i as isize + high).min(m) as usize;
+        for j in first..last+1 {
+            let mut new_cell = if i == 0 && j == 0 { //setup corner
+                Cell::new()
+            } else if local && (i == 0 || j == 0) { //setup edges
+                Cell::new()
+            } else if j == 0 {
+                Cell { d_score: config.d_open(1) + config.g2 * i as i32, i_score: REAL_MIN, s_score: REAL_MIN }
+            } else if i == 0 {
+                Cell { d_score: REAL_MIN, i_score: config.i_open(0) + config.g1 * j as i32, s_score: REAL_MIN }
+            } else { //fill in the inside
+                let up = if in_band(i-1, j) {matrix.index(i-1, column(i-1, j))} else {&outside};
+                let left = if in_band(i, j-1) {matrix.index(i, column(i, j-1))} else {&outside};
+                let diag = matrix.index(i-1, column(i-1, j-1)); //the diagonal neighbour is always in the band
+                let match_score = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+                let mut new_cell = Cell::next(up, left, diag, match_score, config, i);
+                if local {
+                    new_cell.mask_seed(diag, s1.as_bytes()[i-1], s2.as_bytes()[j-1], config);
+                }
+                new_cell
+            };
+
+            if local {
+                new_cell.clamp();
+                if new_cell.score() > top_score {
+                    top_score = new_cell.score();
+                    top_i = i;
+                    top_j = j;
+                }
+            }
+            *matrix.index_mut(i, column(i, j)) = new_cell;
+        }
+    }
+
+    // retrace from the best cell, treating anything outside the band as unreachable
+    let cell = |i: usize, j: usize| if in_band(i, j) {matrix.index(i, column(i, j))} else {&outside};
+    let alignment = if local {
+        let (s1_str, s2_str, i_0, j_0) = retrace(cell, s1, s2, config, top_i, top_j, true);
+        Alignment::from_rows(AlignmentKind::Local, s1_str, s2_str, i_0, j_0, top_score, config)
+    } else {
+        let (s1_str, s2_str, _, _) = retrace(cell, s1, s2, config, s1.len(), s2.len(), false);
+        let score = matrix.index(s1.len(), column(s1.len(), s2.len())).score();
+        Alignment::from_rows(AlignmentKind::Global, s1_str, s2_str, 0, 0, score, config)
+    };
+
+    // walk the path to see whether it ever ran along an edge that has cells beyond it
+    let mut i = alignment.s1_start as isize;
+    let mut j = alignment.s2_start as isize;
+    let mut touched_edge = false;
+    for (&c1, &c2) in alignment.s1_row.as_bytes().iter().zip(alignment.s2_row.as_bytes()) {
+        if c1 != b'-' {
+            i += 1;
+        }
+        if c2 != b'-' {
+            j += 1;
+        }
+        if (j - i == low && low > -n) || (j - i == high && high < m) {
+            touched_edge = true;
+            break;
+        }
+    }
+
+    let covers_everything = low == -n && high == m;
+    let bound = if local {
+        local_outside_bound(s1.len(), s2.len(), low, high, config)
+    } else {
+        outside_bound(s1.len(), s2.len(), low, high, config)
+    };
+    let optimal = covers_everything || alignment.score >= bound;
+    (alignment, BandReport { band, touched_edge, optimal })
+}
+
+
+/// Upper bound on the score of any global alignment that leaves the band.
+/// Reaching offset high+1 and coming back to the final diagonal takes at least 2*(high+1) - (m-n) gap characters
+/// split across both strings, and likewise for low-1, so the bound is every remaining pair scoring the best possible
+/// and every gap costing as little as either string's gaps do anywhere.
+fn outside_bound(n: usize, m: usize, low: isize, high: isize, config: &Config) -> i32 {
+    let n = n as isize;
+    let m = m as isize;
+    let shift = m - n;
+    let mut gaps = isize::MAX;
+    if high < m {
+        gaps = gaps.min(2 * (high + 1) - shift);
+    }
+    if low > -n {
+        gaps = gaps.min(2 * (1 - low) + shift);
+    }
+    if gaps == isize::MAX || gaps > n + m { //there's no way out of the band
+        return i32::MIN;
+    }
+    let pairs = (n + m - gaps) / 2;
+    config.max_substitution().max(0) * pairs as i32 + 2 * config.cheapest_open() + config.g1.max(config.g2) * gaps as i32
+}
+
+
+/// Upper bound on the score of any local alignment that uses a cell outside the band.
+/// One lying wholly outside has no more pairs than the longest diagonal outside the band, each scoring the best possible.
+/// One that crosses the edge of the band has at most min(n,m) pairs and at least one gap, costing as little as any gap can.
+fn local_outside_bound(n: usize, m: usize, low: isize, high: isize, config: &Config) -> i32 {
+    let n = n as isize;
+    let m = m as isize;
+    let mut longest = -1;
+    if high < m {
+        longest = longest.max((m - high - 1).min(n));
+    }
+    if low > -n {
+        longest = longest.max((n + low - 1).min(m));
+    }
+    if longest < 0 { //there's nothing outside the band
+        return i32::MIN;
+    }
+    let best = config.max_substitution().max(0);
+    let crossing = best * n.min(m) as i32 + config.cheapest_open() + config.g1.max(config.g2);
+    (best * longest as i32).max(crossing)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::{needleman_wunsch, smith_waterman};
+    use crate::random::Rng;
+    use crate::testing::{config, random_dna, random_string};
+
+    #[test]
+    fn widened_local_matches_smith_waterman() {
+        let config = config("match = 2\nmismatch = -3\nh1 = -5\ng1 = 0\nh2 = -4\ng2 = -1\n");
+        let (alignment, report) = banded("CCATATGCGC", "ATACTCAACC", &config, true, 1, true);
+        assert_eq!(alignment.score, smith_waterman("CCATATGCGC", "ATACTCAACC", &config).score);
+        assert!(report.optimal);
+
+        let mut rng = Rng::new(11);
+        for _ in 0..500 {
+            let s1 = random_dna(&mut rng, 30);
+            let s2 = random_dna(&mut rng, 30);
+            let band = rng.below(4);
+            assert_eq!(banded(&s1, &s2, &config, true, band, true).0.score, smith_waterman(&s1, &s2, &config).score,
+                "{} vs {} from band {}", s1, s2, band);
+        }
+    }
+
+    #[test]
+    fn widened_local_stops_once_nothing_outside_can_score_more() {
+        // 40 matches beat the 40 - 5 - 1 any path crossing out of the band could reach, so the first band is enough
+        let config = config("match = 1\nmismatch = -3\nh = -5\ng = -1\n");
+        let mut rng = Rng::new(13);
+        let s = random_string(&mut rng, b"ACGT", 40);
+        let (alignment, report) = banded(&s, &s, &config, true, 2, true);
+        assert_eq!((alignment.score, report.band, report.optimal), (40, 2, true));
+
+        // two mismatches leave it short of that bound, so the band has to cover everything before it's known to be optimal
+        let mutated: String = s.bytes().enumerate()
+            .map(|(k, c)| if k == 10 || k == 30 {if c == b'A' {'C'} else {'A'}} else {c as char})
+            .collect();
+        let (alignment, report) = banded(&s, &mutated, &config, true, 2, true);
+        assert_eq!(alignment.score, smith_waterman(&s, &mutated, &config).score);
+        assert!(report.optimal && report.band >= 40);
+    }
+
+    #[test]
+    fn widened_global_matches_needleman_wunsch() {
+        let config = config("match = 1\nmismatch = -2\nh1 = -3\ng1 = -1\nh2 = -6\ng2 = -2\n");
+        let mut rng = Rng::new(12);
+        for _ in 0..500 {
+            let s1 = random_dna(&mut rng, 30);
+            let s2 = random_dna(&mut rng, 30);
+            let (alignment, report) = banded(&s1, &s2, &config, false, rng.below(4), true);
+            assert!(report.optimal);
+            assert_eq!(alignment.score, needleman_wunsch(&s1, &s2, &config).score, "{} vs {}", s1, s2);
+        }
+    }
+}
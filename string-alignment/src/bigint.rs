@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{AddAssign, SubAssign};
+use crate::random::Rng;
+
+
+/// An unsigned integer of any size, for counts that outgrow u64, such as the number of co-optimal alignments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigUint {
+    limbs: Vec<u32>, //least significant first, with no zero limbs at the top, so 0 has none
+}
+impl BigUint {
+
+    /// The number 0
+    pub fn zero() -> Self {
+        BigUint { limbs: Vec::new() }
+    }
+
+    /// The same number as n
+    pub fn from_u64(n: u64) -> Self {
+        let mut number = BigUint { limbs: vec![n as u32, (n >> 32) as u32] };
+        number.trim();
+        number
+    }
+
+    /// Whether this is 0
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// The number if it fits in a u64
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs.len() {
+            0 => Some(0),
+            1 => Some(self.limbs[0] as u64),
+            2 => Some(self.limbs[0] as u64 | (self.limbs[1] as u64) << 32),
+            _ => None,
+        }
+    }
+
+    /// Number of bits needed to write the number down
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            None => 0,
+            Some(top) => 32 * self.limbs.len() - top.leading_zeros() as usize,
+        }
+    }
+
+    /// A uniformly random number from 0 up to but not including this one, which mustn't be 0
+    pub fn random_below(&self, rng: &mut Rng) -> BigUint {
+        assert!(!self.is_zero(), "There's no number below 0 to pick");
+        let top_bits = self.bits() - 32 * (self.limbs.len() - 1);
+        let top_mask = if top_bits == 32 {u32::MAX} else {(1 << top_bits) - 1};
+        loop { //draw numbers with as many bits until one is small enough, which takes at most 2 draws on average
+            let mut limbs: Vec<u32> = (0..self.limbs.len()).map(|_| rng.next_u64() as u32).collect();
+            *limbs.last_mut().unwrap() &= top_mask;
+            let mut number = BigUint { limbs };
+            number.trim();
+            if number < *self {
+                return number;
+            }
+        }
+    }
+
+    /// Drops zero limbs from the top
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    /// Divides in place by a small divisor, returning the remainder
+    fn div_rem_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder: u64 = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = remainder << 32 | *limb as u64;
+            *limb = (value / divisor as u64) as u32;
+            remainder = value % divisor as u64;
+        }
+        self.trim();
+        remainder as u32
+    }
+}
+impl AddAssign<&BigUint> for BigUint {
+    fn add_assign(&mut self, other: &BigUint) {
+        if self.limbs.len() < other.limbs.len() {
+            self.limbs.resize(other.limbs.len(), 0);
+        }
+        let mut carry = 0u64;
+        for (k, limb) in self.limbs.iter_mut().enumerate() {
+            let sum = *limb as u64 + other.limbs.get(k).copied().unwrap_or(0) as u64 + carry;
+            *limb = sum as u32;
+            carry = sum >> 32;
+            if carry == 0 && k >= other.limbs.len() {
+                break;
+            }
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+}
+impl SubAssign<&BigUint> for BigUint {
+    /// Subtracts a number no bigger than this one
+    fn sub_assign(&mut self, other: &BigUint) {
+        assert!(*self >= *other, "BigUint subtraction would go below 0");
+        let mut borrow = 0i64;
+        for (k, limb) in self.limbs.iter_mut().enumerate() {
+            let difference = *limb as i64 - other.limbs.get(k).copied().unwrap_or(0) as i64 - borrow;
+            borrow = (difference < 0) as i64;
+            *limb = (difference + (borrow << 32)) as u32;
+        }
+        self.trim();
+    }
+}
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.len().cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // peel off 9 decimal digits at a time, least significant first
+        let mut rest = self.clone();
+        let mut chunks: Vec<u32> = Vec::new();
+        while !rest.is_zero() {
+            chunks.push(rest.div_rem_small(1_000_000_000));
+        }
+        write!(f, "{}", chunks.last().unwrap())?;
+        for chunk in chunks.iter().rev().skip(1) {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2 to the power of k, by doubling
+    fn power_of_two(k: usize) -> BigUint {
+        let mut number = BigUint::from_u64(1);
+        for _ in 0..k {
+            let copy = number.clone();
+            number += &copy;
+        }
+        number
+    }
+
+    #[test]
+    fn carries_and_borrows_run_across_limbs() {
+        let one = BigUint::from_u64(1);
+        let mut all_ones = power_of_two(96);
+        all_ones -= &one; //borrows through three zero limbs
+        assert_eq!(all_ones.limbs, vec![u32::MAX; 3]);
+        assert_eq!(all_ones.bits(), 96);
+
+        all_ones += &one; //and carries back through all of them
+        assert_eq!(all_ones, power_of_two(96));
+        assert_eq!(all_ones.limbs, vec![0, 0, 0, 1]);
+
+        let mut number = power_of_two(100);
+        number -= &power_of_two(100);
+        assert!(number.is_zero() && number.limbs.is_empty());
+        assert_eq!(BigUint::from_u64(u64::MAX).to_u64(), Some(u64::MAX));
+        assert_eq!(power_of_two(64).to_u64(), None);
+        assert!(power_of_two(64) > BigUint::from_u64(u64::MAX));
+    }
+
+    #[test]
+    fn displays_in_decimal() {
+        assert_eq!(BigUint::zero().to_string(), "0");
+        assert_eq!(BigUint::from_u64(1_000_000_000).to_string(), "1000000000");
+        assert_eq!(BigUint::from_u64(1_000_000_000_000_000_007).to_string(), "1000000000000000007");
+        assert_eq!(power_of_two(64).to_string(), "18446744073709551616");
+        assert_eq!(power_of_two(100).to_string(), "1267650600228229401496703205376");
+    }
+}
@@ -0,0 +1,275 @@
+use crate::algorithms::{best_state, State};
+use crate::dna::translate_codon;
+use crate::structs::{Alignment, AlignmentKind, Cell, Config, Matrix, REAL_MIN};
+
+
+/// Steps into the substitution state that break the reading frame, as (characters of s1, characters of s2) against gaps
+const SHIFTS: [(usize, usize); 4] = [(1, 0), (2, 0), (0, 1), (0, 2)];
+
+
+/// A cell no path reaches
+const UNREACHABLE: Cell = Cell { d_score: REAL_MIN, i_score: REAL_MIN, s_score: REAL_MIN };
+
+
+/// How each of a cell's states was reached, packed into a byte per state so whole coding sequences fit in memory.
+/// Codon and translated alignment both fill one per cell.
+#[derive(Debug, Clone, Default)]
+pub struct CodonTrace {
+    pub s: u8, //how the substitution state was reached, in a code each aligner defines
+    pub d: u8, //previous state, as its state_code
+    pub i: u8,
+}
+
+
+/// Scores of the last 4 rows, all a fill that reaches back one codon, 3 rows, ever needs
+#[derive(Debug, Clone)]
+pub struct CodonRows {
+    rows: Vec<Vec<Cell>>,
+}
+impl CodonRows {
+
+    /// Rows of m+1 cells that nothing reaches yet
+    pub fn new(m: usize) -> Self {
+        CodonRows { rows: vec![vec![UNREACHABLE; m+1]; 4] }
+    }
+
+    /// The cell in row i, which must be one of the last 4 filled
+    #[inline]
+    pub fn get(&self, i: usize, j: usize) -> &Cell {
+        &self.rows[i % 4][j]
+    }
+
+    /// Fills the cell in row i, taking the place of row i-4
+    #[inline]
+    pub fn set(&mut self, i: usize, j: usize, cell: Cell) {
+        self.rows[i % 4][j] = cell;
+    }
+}
+
+
+/// Code of a state in a trace byte
+pub fn state_code(state: State) -> u8 {
+    match state {
+        State::S => 0,
+        State::D => 1,
+        State::I => 2,
+    }
+}
+
+
+/// State a code in a trace byte stands for
+pub fn state_of(code: u8) -> State {
+    match code {
+        0 => State::S,
+        1 => State::D,
+        _ => State::I,
+    }
+}
+
+
+/// Best way into the gap state `gap` from the cell one step back: extending a gap of the same kind for g, or opening one
+/// for h + g from either other state. Returns the score and the state_code it came from, with ties going to S, then D, then I.
+pub fn gap_step(prev: &Cell, gap: State, h: i32, g: i32) -> (i32, u8) {
+    [(State::S, prev.s_score), (State::D, prev.d_score), (State::I, prev.i_score)].into_iter()
+        .map(|(from, score)| (score + if from == gap {g} else {h + g}, state_code(from)))
+        .max_by_key(|&(score, code)| (score, -(code as i32)))
+        .unwrap()
+}
+
+
+/// Amino acid of the codon ending at each position of a DNA string, with X where no whole codon ends yet
+pub fn codon_translations(s: &str) -> Vec<u8> {
+    (0..s.len()+1).map(|end| if end < 3 {b'X'} else {translate_codon(&s.as_bytes()[end-3..end])}).collect()
+}
+
+
+/// One column of codons in a codon alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Codon, //a codon of s1 against a codon of s2
+    S1Codon, //a codon of s1 against a gap
+    S2Codon, //a codon of s2 against a gap
+    Shift(usize, usize), //one or two characters of one string against a gap, shifting the frame
+}
+
+
+/// What a codon alignment did with the codons of the two coding sequences
+#[derive(Debug, Clone, Default)]
+pub struct CodonReport {
+    pub codons: usize, //codons aligned against codons
+    pub identical: usize,
+    pub synonymous: usize, //different codons for the same amino acid
+    pub nonsynonymous: usize, //codons for different amino acids
+    pub codon_gaps: usize, //runs of whole codons against gaps
+    pub gapped_codons: usize, //codons in those runs
+    pub frameshifts: usize,
+}
+
+
+/// Globally aligns two coding sequences a codon at a time. Pairs of codons score like their amino acids under the config,
+/// a gap of k codons costs h + g*k, and gaps of 1 or 2 characters, which break the frame, cost `frameshift` each.
+pub fn codon_align(s1: &str, s2: &str, config: &Config, frameshift: i32) -> (Alignment, CodonReport) {
+    let n = s1.len();
+    let m = s2.len();
+    let aa1 = codon_translations(s1);
+    let aa2 = codon_translations(s2);
+
+    let mut rows = CodonRows::new(m);
+    let mut trace: Matrix<CodonTrace> = Matrix::with_shape(n+1, m+1);
+    for (i, &s1_aa) in aa1.iter().enumerate() {
+        for (j, &s2_aa) in aa2.iter().enumerate() {
+            let mut cell = UNREACHABLE;
+            if i == 0 && j == 0 {
+                cell.s_score = 0;
+                rows.set(0, 0, cell);
+                continue;
+            }
+            let mut step = CodonTrace::default();
+
+            // a pair of codons, or a frameshift, from the best state
+            if i >= 3 && j >= 3 {
+                let prev = rows.get(i-3, j-3);
+                let state = best_state(prev);
+                cell.s_score = prev.score() + config.substitution(s1_aa, s2_aa);
+                step.s = state_code(state);
+            }
+            for (k, &(di, dj)) in SHIFTS.iter().enumerate() {
+                if i >= di && j >= dj {
+                    let prev = rows.get(i-di, j-dj);
+                    if prev.score() + frameshift > cell.s_score {
+                        cell.s_score = prev.score() + frameshift;
+                        step.s = 3 * (k as u8 + 1) + state_code(best_state(prev));
+                    }
+                }
+            }
+
+            // a codon of s1 against a gap, opening a new gap unless it extends one
+            if i >= 3 {
+                (cell.d_score, step.d) = gap_step(rows.get(i-3, j), State::D, config.h2, config.g2);
+            }
+
+            // a codon of s2 against a gap
+            if j >= 3 {
+                (cell.i_score, step.i) = gap_step(rows.get(i, j-3), State::I, config.h1, config.g1);
+            }
+
+            rows.set(i, j, cell);
+            *trace.index_mut(i, j) = step;
+        }
+    }
+    let last = rows.get(n, m);
+    let score = last.score();
+
+    // follow the trace back from the corner
+    let mut steps: Vec<Step> = Vec::new();
+    let (mut i, mut j) = (n, m);
+    let mut state = best_state(last);
+    while i != 0 || j != 0 {
+        let step = trace.index(i, j);
+        let (taken, prev) = match state {
+            State::S if step.s < 3 => (Step::Codon, step.s),
+            State::S => {
+                let (di, dj) = SHIFTS[(step.s / 3 - 1) as usize];
+                (Step::Shift(di, dj), step.s % 3)
+            },
+            State::D => (Step::S1Codon, step.d),
+            State::I => (Step::S2Codon, step.i),
+        };
+        let (di, dj) = match taken {
+            Step::Codon => (3, 3),
+            Step::S1Codon => (3, 0),
+            Step::S2Codon => (0, 3),
+            Step::Shift(di, dj) => (di, dj),
+        };
+        i -= di;
+        j -= dj;
+        steps.push(taken);
+        state = state_of(prev);
+    }
+    steps.reverse();
+
+    // lay out the rows and count what happened to the codons
+    let mut s1_row = String::with_capacity(n + m);
+    let mut s2_row = String::with_capacity(n + m);
+    let mut report = CodonReport::default();
+    let (mut i, mut j) = (0, 0);
+    let mut last_step: Option<Step> = None;
+    for &step in &steps {
+        match step {
+            Step::Codon => {
+                let (c1, c2) = (&s1[i..i+3], &s2[j..j+3]);
+                s1_row += c1;
+                s2_row += c2;
+                report.codons += 1;
+                if c1.eq_ignore_ascii_case(c2) {
+                    report.identical += 1;
+                } else if aa1[i+3] == aa2[j+3] {
+                    report.synonymous += 1;
+                } else {
+                    report.nonsynonymous += 1;
+                }
+                i += 3;
+                j += 3;
+            },
+            Step::S1Codon | Step::S2Codon => {
+                if last_step != Some(step) {
+                    report.codon_gaps += 1;
+                }
+                report.gapped_codons += 1;
+                if step == Step::S1Codon {
+                    s1_row += &s1[i..i+3];
+                    s2_row += "---";
+                    i += 3;
+                } else {
+                    s1_row += "---";
+                    s2_row += &s2[j..j+3];
+                    j += 3;
+                }
+            },
+            Step::Shift(di, dj) => {
+                report.frameshifts += 1;
+                s1_row += if di > 0 {&s1[i..i+di]} else {&"--"[..dj]};
+                s2_row += if dj > 0 {&s2[j..j+dj]} else {&"--"[..di]};
+                i += di;
+                j += dj;
+            },
+        }
+        last_step = Some(step);
+    }
+
+    (Alignment::from_rows(AlignmentKind::Codon, s1_row, s2_row, 0, 0, score, config), report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::config;
+
+    const SCORES: &str = "match = 5\nmismatch = -4\nh = -6\ng = -2\n";
+
+    #[test]
+    fn synonymous_codons_are_counted() {
+        // ATG AAA CTG TTT GAT against ATG AAG CTC TTT GAA: M K L F D against M K L F E
+        let (alignment, report) = codon_align("ATGAAACTGTTTGAT", "ATGAAGCTCTTTGAA", &config(SCORES), -10);
+        assert_eq!((alignment.s1_row.as_str(), alignment.s2_row.as_str()), ("ATGAAACTGTTTGAT", "ATGAAGCTCTTTGAA"));
+        assert_eq!((report.codons, report.identical, report.synonymous, report.nonsynonymous), (5, 2, 2, 1));
+        assert_eq!((report.codon_gaps, report.frameshifts), (0, 0));
+        assert_eq!(alignment.score, 4 * 5 - 4);
+    }
+
+    #[test]
+    fn whole_codon_gaps_and_frameshifts() {
+        // the codon CCC is missing from s2
+        let (alignment, report) = codon_align("ATGAAACCCGGGTTT", "ATGAAAGGGTTT", &config(SCORES), -10);
+        assert_eq!((alignment.s1_row.as_str(), alignment.s2_row.as_str()), ("ATGAAACCCGGGTTT", "ATGAAA---GGGTTT"));
+        assert_eq!((report.codons, report.codon_gaps, report.gapped_codons, report.frameshifts), (4, 1, 1, 0));
+        assert_eq!(alignment.score, 4 * 5 - 6 - 2);
+
+        // s2 has one extra C, so the frame shifts once and the rest of s2 still lines up a codon at a time
+        let (alignment, report) = codon_align("ATGAAAGGGTTT", "ATGAAACGGGTTT", &config(SCORES), -10);
+        assert_eq!((alignment.s1_row.as_str(), alignment.s2_row.as_str()), ("ATGAAA-GGGTTT", "ATGAAACGGGTTT"));
+        assert_eq!((report.codons, report.identical, report.codon_gaps, report.frameshifts), (4, 4, 0, 1));
+        assert_eq!(alignment.score, 4 * 5 - 10);
+    }
+}
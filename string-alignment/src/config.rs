@@ -0,0 +1,303 @@
+use std::fmt;
+use crate::gaps::GapModel;
+use crate::matrices::{SubstitutionMatrix, IUPAC};
+use crate::structs::Config;
+
+
+/// Name of the profile made of the settings before any [section]
+pub const DEFAULT_PROFILE: &str = "default";
+
+
+/// A problem with a config file, pointing at the line it's on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize, //1-based, or 0 when it isn't about any one line
+    pub message: String,
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+
+/// One setting and the line it was given on
+#[derive(Debug, Clone)]
+struct Setting<T> {
+    value: T,
+    line: usize,
+}
+
+
+/// Everything one profile sets, leaving the rest to the default profile and then the built-in defaults
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    true_match: Option<Setting<i32>>,
+    mismatch: Option<Setting<i32>>,
+    matrix: Option<Setting<String>>,
+    h: Option<Setting<i32>>, //both strings' gaps, unless h1 or h2 says otherwise
+    g: Option<Setting<i32>>,
+    h1: Option<Setting<i32>>,
+    g1: Option<Setting<i32>>,
+    h2: Option<Setting<i32>>,
+    g2: Option<Setting<i32>>,
+    gap_model: Option<Setting<String>>,
+    h_long: Option<Setting<i32>>, //the long-gap line of the two-piece model, for both strings unless h1-long or h2-long says otherwise
+    g_long: Option<Setting<i32>>,
+    h1_long: Option<Setting<i32>>,
+    g1_long: Option<Setting<i32>>,
+    h2_long: Option<Setting<i32>>,
+    g2_long: Option<Setting<i32>>,
+    ignore_case: Option<Setting<bool>>,
+    mask_seeds: Option<Setting<bool>>,
+}
+
+
+/// A parsed config file: the default profile and any number of named [profiles].
+/// Lines are `key = value` or the older `key value`, '#' starts a comment, and blank lines are skipped.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    profiles: Vec<(String, Settings)>, //the default profile always comes first
+}
+impl ConfigFile {
+
+    /// Parses a whole config file
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut profiles: Vec<(String, Settings)> = vec![(String::from(DEFAULT_PROFILE), Settings::default())];
+
+        for (num, raw) in text.lines().enumerate() {
+            let line = num + 1;
+            let error = |message: String| ConfigError { line, message };
+            let content = raw.split('#').next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            // a new profile
+            if let Some(header) = content.strip_prefix('[') {
+                let name = header.strip_suffix(']')
+                    .ok_or_else(|| error(format!("profile header \"{}\" is missing its closing ']'", content)))?
+                    .trim();
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                    return Err(error(format!("profile name \"{}\" may only use letters, digits, '-' and '_'", name)));
+                }
+                if profiles.iter().any(|(existing, _)| existing == name) {
+                    return Err(error(format!("profile \"{}\" is defined twice", name)));
+                }
+                profiles.push((String::from(name), Settings::default()));
+                continue;
+            }
+
+            // a setting in the current profile
+            let (key, value) = match content.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => content.split_once(char::is_whitespace)
+                    .map(|(key, value)| (key.trim(), value.trim()))
+                    .ok_or_else(|| error(format!("expected \"key = value\", found \"{}\"", content)))?,
+            };
+            if value.is_empty() {
+                return Err(error(format!("{} has no value", key)));
+            }
+            let settings = &mut profiles.last_mut().unwrap().1;
+            let integer = |value: &str| value.parse::<i32>()
+                .map(|value| Some(Setting { value, line }))
+                .map_err(|_| error(format!("{} must be a whole number, found \"{}\"", key, value)));
+            let switch = |value: &str| match value {
+                "true" | "yes" | "on" => Ok(Some(Setting { value: true, line })),
+                "false" | "no" | "off" => Ok(Some(Setting { value: false, line })),
+                _ => Err(error(format!("{} must be true or false, found \"{}\"", key, value))),
+            };
+            let penalty = |value: &str| match integer(value)? {
+                Some(setting) if setting.value > 0 => Err(error(format!("{} is a gap penalty, so it can't be positive, found {}", key, setting.value))),
+                setting => Ok(setting),
+            };
+            match key {
+                "match" => settings.true_match = integer(value)?,
+                "mismatch" => settings.mismatch = integer(value)?,
+                "matrix" => settings.matrix = Some(Setting { value: String::from(unquote(value)), line }),
+                "h" => settings.h = penalty(value)?,
+                "g" => settings.g = penalty(value)?,
+                "h1" => settings.h1 = penalty(value)?,
+                "g1" => settings.g1 = penalty(value)?,
+                "h2" => settings.h2 = penalty(value)?,
+                "g2" => settings.g2 = penalty(value)?,
+                "gap-model" => settings.gap_model = Some(Setting { value: String::from(unquote(value)), line }),
+                "h-long" => settings.h_long = penalty(value)?,
+                "g-long" => settings.g_long = penalty(value)?,
+                "h1-long" => settings.h1_long = penalty(value)?,
+                "g1-long" => settings.g1_long = penalty(value)?,
+                "h2-long" => settings.h2_long = penalty(value)?,
+                "g2-long" => settings.g2_long = penalty(value)?,
+                "ignore-case" => settings.ignore_case = switch(value)?,
+                "mask-seeds" => settings.mask_seeds = switch(value)?,
+                _ => return Err(error(format!("unknown setting \"{}\", expected match, mismatch, matrix, h, g, h1, g1, h2, g2, gap-model, h-long, g-long, h1-long, g1-long, h2-long, g2-long, ignore-case or mask-seeds", key))),
+            }
+        }
+        Ok(ConfigFile { profiles })
+    }
+
+    /// Names of every profile, starting with the default one
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Builds the config for a profile, loading its matrix if it has one. The IUPAC matrix is built from the profile's match and mismatch.
+    /// Named profiles start from the default profile's settings, which start from the built-in ones.
+    /// A gap penalty for one string (h1, g1, h2, g2, h1-long...) wins over the general one (h, g, h-long...) from the same profile.
+    pub fn config(&self, profile: &str) -> Result<Config, ConfigError> {
+        let settings = &self.profiles.iter()
+            .find(|(name, _)| name == profile)
+            .ok_or_else(|| ConfigError {
+                line: 0,
+                message: format!("there is no profile \"{}\", the profiles are: {}", profile, self.profile_names().join(", ")),
+            })?
+            .1;
+        let defaults = &self.profiles[0].1;
+        let pick = |own: &Option<Setting<i32>>, default: &Option<Setting<i32>>, builtin: i32| {
+            own.as_ref().or(default.as_ref()).map_or(builtin, |setting| setting.value)
+        };
+        let side = |own_side: &Option<Setting<i32>>, own: &Option<Setting<i32>>, default_side: &Option<Setting<i32>>, default: &Option<Setting<i32>>| {
+            own_side.as_ref().or(own.as_ref()).or(default_side.as_ref()).or(default.as_ref()).map(|setting| setting.value)
+        };
+        let gap = |own_side: &Option<Setting<i32>>, own: &Option<Setting<i32>>, default_side: &Option<Setting<i32>>, default: &Option<Setting<i32>>, builtin: i32| {
+            side(own_side, own, default_side, default).unwrap_or(builtin)
+        };
+        let switch = |own: &Option<Setting<bool>>, default: &Option<Setting<bool>>| {
+            own.as_ref().or(default.as_ref()).is_some_and(|setting| setting.value)
+        };
+
+        let true_match = pick(&settings.true_match, &defaults.true_match, 1);
+        let mismatch = pick(&settings.mismatch, &defaults.mismatch, -2);
+        let matrix = match settings.matrix.as_ref().or(defaults.matrix.as_ref()) {
+            None => None,
+            Some(setting) if setting.value.eq_ignore_ascii_case(IUPAC) => Some(SubstitutionMatrix::iupac(true_match, mismatch)),
+            Some(setting) => Some(SubstitutionMatrix::load(&setting.value)
+                .map_err(|message| ConfigError { line: setting.line, message })?),
+        };
+        let gap_model = match settings.gap_model.as_ref().or(defaults.gap_model.as_ref()) {
+            None => GapModel::Affine,
+            Some(setting) => {
+                let long1 = side(&settings.h1_long, &settings.h_long, &defaults.h1_long, &defaults.h_long)
+                    .zip(side(&settings.g1_long, &settings.g_long, &defaults.g1_long, &defaults.g_long));
+                let long2 = side(&settings.h2_long, &settings.h_long, &defaults.h2_long, &defaults.h_long)
+                    .zip(side(&settings.g2_long, &settings.g_long, &defaults.g2_long, &defaults.g_long));
+                let long = long1.zip(long2);
+                GapModel::parse(&setting.value, long).map_err(|message| ConfigError { line: setting.line, message })?
+            },
+        };
+        Ok(Config {
+            true_match,
+            mismatch,
+            matrix,
+            h1: gap(&settings.h1, &settings.h, &defaults.h1, &defaults.h, -5),
+            g1: gap(&settings.g1, &settings.g, &defaults.g1, &defaults.g, -1),
+            h2: gap(&settings.h2, &settings.h, &defaults.h2, &defaults.h, -5),
+            g2: gap(&settings.g2, &settings.g, &defaults.g2, &defaults.g, -1),
+            gap_track: None,
+            gap_model,
+            ignore_case: switch(&settings.ignore_case, &defaults.ignore_case),
+            mask_seeds: switch(&settings.mask_seeds, &defaults.mask_seeds),
+            s1_name: String::new(),
+            s2_name: String::new(),
+        })
+    }
+}
+
+
+/// Parses a gap track: one gap-open multiplier per character of s1, separated by whitespace, with '#' starting a comment.
+/// A multiplier of 0.5 halves the cost of opening a gap at that character, 2 doubles it.
+pub fn parse_gap_track(text: &str) -> Result<Vec<f64>, String> {
+    let mut track: Vec<f64> = Vec::new();
+    for (num, raw) in text.lines().enumerate() {
+        for word in raw.split('#').next().unwrap_or("").split_whitespace() {
+            match word.parse::<f64>() {
+                Ok(multiplier) if multiplier.is_finite() && multiplier >= 0.0 => track.push(multiplier),
+                _ => return Err(format!("line {}: \"{}\" is not a gap-open multiplier, expected a number of at least 0", num + 1, word)),
+            }
+        }
+    }
+    if track.is_empty() {
+        return Err(String::from("gap track is empty"));
+    }
+    Ok(track)
+}
+
+
+/// Strips one pair of matching quotes from a value
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_line_is_reported_with_its_number() {
+        let error = ConfigFile::parse("match = 1\n\n# a comment\nmismatch\n").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(error.to_string(), "line 4: expected \"key = value\", found \"mismatch\"");
+
+        let error = ConfigFile::parse("match = 1\n[dna\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        let error = ConfigFile::parse("match = one\n").unwrap_err();
+        assert_eq!(error.line, 1);
+        let error = ConfigFile::parse("h = -5\nspeed = 3\n").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn positive_gap_penalty_is_rejected() {
+        for key in ["h", "g", "h1", "g2", "h-long", "g2-long"] {
+            let error = ConfigFile::parse(&format!("match = 1\n{} = 2\n", key)).unwrap_err();
+            assert_eq!(error.line, 2);
+            assert!(error.message.contains("can't be positive"), "{}", error.message);
+        }
+        assert!(ConfigFile::parse("h = 0\ng = -1\n").is_ok());
+    }
+
+    #[test]
+    fn unknown_profile_lists_the_others() {
+        let file = ConfigFile::parse("match = 1\n[dna]\nmismatch = -3\n").unwrap();
+        let error = file.config("protein").unwrap_err();
+        assert_eq!(error.line, 0);
+        assert_eq!(error.to_string(), "there is no profile \"protein\", the profiles are: default, dna");
+        assert!(ConfigFile::parse("[dna]\n[dna]\n").is_err());
+    }
+
+    #[test]
+    fn profiles_inherit_and_override() {
+        let file = ConfigFile::parse("match = 2\nmismatch = -3\nh = -6\ng2 = -3\nignore-case = true\n\n[strict]\nmismatch = -5\ng = -2\nh1 = -8\n").unwrap();
+        assert_eq!(file.profile_names(), vec!["default", "strict"]);
+
+        let config = file.config(DEFAULT_PROFILE).unwrap();
+        assert_eq!((config.true_match, config.mismatch), (2, -3));
+        assert_eq!((config.h1, config.g1, config.h2, config.g2), (-6, -1, -6, -3));
+        assert!(config.ignore_case && !config.mask_seeds);
+
+        let config = file.config("strict").unwrap();
+        assert_eq!((config.true_match, config.mismatch), (2, -5));
+        assert_eq!((config.h1, config.g1, config.h2, config.g2), (-8, -2, -6, -2)); //the profile's own g beats the default's g2
+        assert!(config.ignore_case);
+        assert_eq!(config.gap_model, GapModel::Affine);
+    }
+
+    #[test]
+    fn two_piece_lines_per_string() {
+        let file = ConfigFile::parse("gap-model = two-piece\nh-long = -20\ng-long = -1\n[split]\ng2-long = 0\n").unwrap();
+        assert_eq!(file.config("split").unwrap().gap_model, GapModel::TwoPiece { h1: -20, g1: -1, h2: -20, g2: 0 });
+        let file = ConfigFile::parse("h = -5\n[two]\ngap-model = two-piece\nh1-long = -20\n").unwrap();
+        let error = file.config("two").unwrap_err();
+        assert_eq!(error.line, 3);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::algorithms::State;
+use crate::bigint::BigUint;
+use crate::random::Rng;
+use crate::structs::{Alignment, AlignmentKind, Cell, Config, Matrix};
+use crate::wavefront::wavefront_fill;
+
+
+/// One state of one cell of the matrix, a node of the graph optimal paths run through
+type Node = (usize, usize, State);
+
+
+/// Every optimal global or local alignment of two strings, as the paths through the filled matrix that reach the
+/// optimal score. Alignments count as different when their rows differ, and local ones start at the pair right after
+/// an empty prefix, as the retrace does, so leading stretches scoring 0 don't make more of them.
+pub struct CoOptimal<'a> {
+    s1: &'a str,
+    s2: &'a str,
+    config: &'a Config,
+    local: bool,
+    matrix: Matrix<Cell>,
+    ways: Matrix<[BigUint; 3]>, //optimal paths from a start into each state of each cell
+    ends: Vec<Node>, //states the optimal paths end in
+    score: i32,
+}
+impl<'a> CoOptimal<'a> {
+
+    /// Bytes the matrix and the counts take for strings of lengths n and m, at least, since counts that outgrow
+    /// their first limbs take more
+    pub fn bytes_needed(n: usize, m: usize) -> usize {
+        (n+1) * (m+1) * (size_of::<Cell>() + size_of::<[BigUint; 3]>() + 3 * size_of::<u32>())
+    }
+
+    /// Fills the matrix and counts the optimal paths into every state, forward from the starts
+    pub fn new(s1: &'a str, s2: &'a str, config: &'a Config, local: bool, threads: usize) -> Self {
+        let n = s1.len();
+        let m = s2.len();
+        let matrix = wavefront_fill(s1, s2, config, local, threads);
+        let mut co_optimal = CoOptimal {
+            s1,
+            s2,
+            config,
+            local,
+            matrix,
+            ways: Matrix::with_shape(n+1, m+1),
+            ends: Vec::new(),
+            score: 0,
+        };
+
+        for i in 0..n+1 {
+            for j in 0..m+1 {
+                let mut ways: [BigUint; 3] = Default::default();
+                for state in [State::S, State::D, State::I] {
+                    let count = &mut ways[index(state)];
+                    for pred in co_optimal.predecessors((i, j, state)) {
+                        match pred {
+                            None => *count += &BigUint::from_u64(1),
+                            Some((pi, pj, ps)) => *count += &co_optimal.ways.index(pi, pj)[index(ps)],
+                        }
+                    }
+                }
+                *co_optimal.ways.index_mut(i, j) = ways;
+            }
+        }
+
+        // global paths end in the corner, local ones wherever the best score is
+        if local {
+            for i in 1..n+1 {
+                for j in 1..m+1 {
+                    let cell = co_optimal.matrix.index(i, j);
+                    if cell.score() > co_optimal.score {
+                        co_optimal.score = cell.score();
+                        co_optimal.ends.clear();
+                    }
+                    if cell.score() == co_optimal.score && co_optimal.score > 0 {
+                        co_optimal.ends.extend(states(cell, cell.score()).map(|state| (i, j, state)));
+                    }
+                }
+            }
+        } else {
+            let cell = co_optimal.matrix.index(n, m);
+            co_optimal.score = cell.score();
+            co_optimal.ends = if n == 0 && m == 0 {vec![(0, 0, State::S)]} else {states(cell, cell.score()).map(|state| (n, m, state)).collect()};
+        }
+        co_optimal.ends.retain(|&(i, j, state)| !co_optimal.ways.index(i, j)[index(state)].is_zero());
+        co_optimal
+    }
+
+    /// The optimal score
+    pub fn score(&self) -> i32 {
+        self.score
+    }
+
+    /// Number of different optimal alignments
+    pub fn count(&self) -> BigUint {
+        let mut total = BigUint::zero();
+        for &(i, j, state) in &self.ends {
+            total += &self.ways.index(i, j)[index(state)];
+        }
+        total
+    }
+
+    /// Up to `limit` different optimal alignments, in the order a depth-first walk back from the ends finds them
+    pub fn enumerate(&self, limit: usize) -> Vec<Alignment> {
+        let mut alignments: Vec<Alignment> = Vec::new();
+        let mut path: Vec<Node> = Vec::new(); //nodes from the end back to the one being visited
+        let mut stack: Vec<(Option<Node>, usize)> = self.ends.iter().rev().map(|&end| (Some(end), 0)).collect(); //nodes to visit and their depth
+        while let Some((node, depth)) = stack.pop() {
+            if alignments.len() >= limit {
+                break;
+            }
+            path.truncate(depth);
+            match node {
+                None => alignments.push(self.alignment(&path)),
+                Some(node) => {
+                    path.push(node);
+                    for pred in self.predecessors(node).into_iter().rev() {
+                        if pred.is_none_or(|(pi, pj, ps)| !self.ways.index(pi, pj)[index(ps)].is_zero()) {
+                            stack.push((pred, depth + 1));
+                        }
+                    }
+                },
+            }
+        }
+        alignments
+    }
+
+    /// One optimal alignment picked uniformly at random from all of them, by walking back from the ends and
+    /// taking each step with probability in proportion to the number of optimal paths through it
+    pub fn sample(&self, rng: &mut Rng) -> Option<Alignment> {
+        let total = self.count();
+        if total.is_zero() {
+            return None;
+        }
+        let ways = |node: Option<Node>| match node {
+            None => BigUint::from_u64(1),
+            Some((i, j, state)) => self.ways.index(i, j)[index(state)].clone(),
+        };
+        let pick = |options: Vec<Option<Node>>, total: &BigUint, rng: &mut Rng| -> Option<Node> {
+            let mut target = total.random_below(rng);
+            for &option in &options {
+                let count = ways(option);
+                if target < count {
+                    return option;
+                }
+                target -= &count;
+            }
+            unreachable!("the ways into a node add up to the ways through its predecessors")
+        };
+
+        let mut path: Vec<Node> = Vec::new();
+        let mut node = pick(self.ends.iter().map(|&end| Some(end)).collect(), &total, rng);
+        while let Some(current) = node {
+            path.push(current);
+            node = pick(self.predecessors(current), &ways(Some(current)), rng);
+        }
+        Some(self.alignment(&path))
+    }
+
+    /// States a path can step back into from a node, all of whose paths stay optimal. None means the path starts here.
+    fn predecessors(&self, (i, j, state): Node) -> Vec<Option<Node>> {
+        let config = self.config;
+        let cur = self.matrix.index(i, j);
+        let mut preds: Vec<Option<Node>> = Vec::new();
+        let usable = |cell: &Cell, state: State| !self.local || value(cell, state) > 0; //local paths only pass through positive states
+
+        // paths start in the corner, and run along the edges of a global matrix in one gap
+        if i == 0 && j == 0 {
+            if state == State::S && !self.local {
+                preds.push(None);
+            }
+            return preds;
+        }
+        if i == 0 || j == 0 {
+            if !self.local && (state == State::D && j == 0 || state == State::I && i == 0) {
+                preds.push(Some(if i + j == 1 {(0, 0, State::S)} else {(i - (j == 0) as usize, j - (i == 0) as usize, state)}));
+            }
+            return preds;
+        }
+
+        match state {
+            State::S => {
+                let diag = self.matrix.index(i-1, j-1);
+                let pair = config.substitution(self.s1.as_bytes()[i-1], self.s2.as_bytes()[j-1]);
+                if pair + diag.score() != cur.s_score || (self.local && cur.s_score <= 0) {
+                    return preds;
+                }
+                if self.local && diag.score() == 0 { //the pair right after an empty prefix starts the alignment
+                    preds.push(None);
+                } else if i == 1 && j == 1 { //the corner only has the one start
+                    preds.push(Some((0, 0, State::S)));
+                } else {
+                    preds.extend(states(diag, diag.score()).map(|prev| Some((i-1, j-1, prev))));
+                }
+            },
+            State::D => {
+                let up = self.matrix.index(i-1, j);
+                let open = config.d_open(i) + config.g2;
+                for (prev, step) in [(State::S, open), (State::D, config.g2), (State::I, open)] {
+                    if value(up, prev) + step == cur.d_score && usable(up, prev) {
+                        preds.push(Some((i-1, j, prev)));
+                    }
+                }
+            },
+            State::I => {
+                let left = self.matrix.index(i, j-1);
+                let open = config.i_open(i) + config.g1;
+                for (prev, step) in [(State::S, open), (State::D, open), (State::I, config.g1)] {
+                    if value(left, prev) + step == cur.i_score && usable(left, prev) {
+                        preds.push(Some((i, j-1, prev)));
+                    }
+                }
+            },
+        }
+        if self.local && value(cur, state) <= 0 {
+            preds.clear();
+        }
+        preds
+    }
+
+    /// Lays out a path given as its nodes from the end back to the start, each one a column of the alignment but the corner
+    fn alignment(&self, path: &[Node]) -> Alignment {
+        let path: Vec<Node> = path.iter().copied().filter(|&(i, j, _)| i > 0 || j > 0).collect();
+        let mut s1_row = String::with_capacity(path.len());
+        let mut s2_row = String::with_capacity(path.len());
+        for &(i, j, state) in path.iter().rev() {
+            s1_row.push(if state == State::I {'-'} else {self.s1.as_bytes()[i-1] as char});
+            s2_row.push(if state == State::D {'-'} else {self.s2.as_bytes()[j-1] as char});
+        }
+        let (s1_start, s2_start) = match path.last() { //the first column's characters are where it starts
+            None => (0, 0),
+            Some(&(i, j, state)) => (i - (state != State::I) as usize, j - (state != State::D) as usize),
+        };
+        let kind = if self.local {AlignmentKind::Local} else {AlignmentKind::Global};
+        Alignment::from_rows(kind, s1_row, s2_row, s1_start, s2_start, self.score, self.config)
+    }
+}
+
+
+/// Position of a state in a cell's array of counts
+fn index(state: State) -> usize {
+    match state {
+        State::S => 0,
+        State::D => 1,
+        State::I => 2,
+    }
+}
+
+
+/// Score of one state of a cell
+fn value(cell: &Cell, state: State) -> i32 {
+    match state {
+        State::S => cell.s_score,
+        State::D => cell.d_score,
+        State::I => cell.i_score,
+    }
+}
+
+
+/// States of a cell that have the given score, in the order the retrace prefers them
+fn states(cell: &Cell, score: i32) -> impl Iterator<Item = State> + '_ {
+    [State::S, State::D, State::I].into_iter().filter(move |&state| value(cell, state) == score)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::algorithms::score_rows;
+    use crate::testing::{config, random_up_to};
+
+    /// Every global alignment of the two strings, as its pair of rows
+    fn all_alignments(s1: &[u8], s2: &[u8], s1_row: &mut String, s2_row: &mut String, rows: &mut Vec<(String, String)>) {
+        if s1.is_empty() && s2.is_empty() {
+            rows.push((s1_row.clone(), s2_row.clone()));
+            return;
+        }
+        for (k1, k2) in [(1, 1), (1, 0), (0, 1)] {
+            if s1.len() < k1 || s2.len() < k2 {
+                continue;
+            }
+            s1_row.push(if k1 == 1 {s1[0] as char} else {'-'});
+            s2_row.push(if k2 == 1 {s2[0] as char} else {'-'});
+            all_alignments(&s1[k1..], &s2[k2..], s1_row, s2_row, rows);
+            s1_row.pop();
+            s2_row.pop();
+        }
+    }
+
+    fn configs() -> Vec<Config> {
+        ["match = 1\nmismatch = -1\nh = -2\ng = -1\n", "match = 2\nmismatch = -3\nh1 = -5\ng1 = 0\nh2 = -4\ng2 = -1\n", "match = 1\nmismatch = 0\nh = 0\ng = 0\n"]
+            .iter().map(|text| config(text)).collect()
+    }
+
+    #[test]
+    fn count_matches_brute_force() {
+        let mut rng = Rng::new(7);
+        for config in configs() {
+            for _ in 0..60 {
+                let s1 = random_up_to(&mut rng, b"ACG", 6);
+                let s2 = random_up_to(&mut rng, b"ACG", 6);
+                let mut rows = Vec::new();
+                all_alignments(s1.as_bytes(), s2.as_bytes(), &mut String::new(), &mut String::new(), &mut rows);
+                let scores: Vec<i32> = rows.iter().map(|(s1_row, s2_row)| score_rows(s1_row, s2_row, &config)).collect();
+                let best = *scores.iter().max().unwrap();
+                let co_optimal = CoOptimal::new(&s1, &s2, &config, false, 1);
+                assert_eq!(co_optimal.score(), best);
+                assert_eq!(co_optimal.count().to_u64(), Some(scores.iter().filter(|&&score| score == best).count() as u64), "{} vs {}", s1, s2);
+            }
+        }
+    }
+
+    #[test]
+    fn enumerated_alignments_are_distinct_and_optimal() {
+        let mut rng = Rng::new(8);
+        for config in configs() {
+            for local in [false, true] {
+                for _ in 0..60 {
+                    let s1 = random_up_to(&mut rng, b"ACG", 8);
+                    let s2 = random_up_to(&mut rng, b"ACG", 8);
+                    let co_optimal = CoOptimal::new(&s1, &s2, &config, local, 1);
+                    let alignments = co_optimal.enumerate(usize::MAX);
+                    assert_eq!(co_optimal.count().to_u64(), Some(alignments.len() as u64), "{} vs {}", s1, s2);
+                    let mut seen = HashSet::new();
+                    for alignment in &alignments {
+                        assert!(seen.insert((alignment.s1_start, alignment.s2_start, alignment.s1_row.clone(), alignment.s2_row.clone())));
+                        assert_eq!(score_rows(&alignment.s1_row, &alignment.s2_row, &config), co_optimal.score());
+                        if !local {
+                            assert_eq!(alignment.s1_row.replace('-', ""), s1);
+                            assert_eq!(alignment.s2_row.replace('-', ""), s2);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_optimal_and_reach_every_alignment() {
+        let mut rng = Rng::new(9);
+        for config in configs() {
+            for local in [false, true] {
+                for _ in 0..30 {
+                    let s1 = random_up_to(&mut rng, b"ACG", 7);
+                    let s2 = random_up_to(&mut rng, b"ACG", 7);
+                    let co_optimal = CoOptimal::new(&s1, &s2, &config, local, 1);
+                    let key = |alignment: &Alignment| (alignment.s1_start, alignment.s2_start, alignment.s1_row.clone(), alignment.s2_row.clone());
+                    let all: HashSet<_> = co_optimal.enumerate(usize::MAX).iter().map(key).collect();
+                    if all.is_empty() || all.len() > 30 {
+                        continue;
+                    }
+                    let mut reached = HashSet::new();
+                    for _ in 0..50 * all.len() {
+                        let alignment = co_optimal.sample(&mut rng).unwrap();
+                        assert_eq!(alignment.score, co_optimal.score());
+                        assert_eq!(score_rows(&alignment.s1_row, &alignment.s2_row, &config), co_optimal.score(), "{} vs {}", s1, s2);
+                        assert!(all.contains(&key(&alignment)), "{} vs {}", s1, s2);
+                        reached.insert(key(&alignment));
+                    }
+                    assert_eq!(reached.len(), all.len(), "{} vs {}", s1, s2);
+                }
+            }
+        }
+    }
+}
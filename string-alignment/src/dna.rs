@@ -0,0 +1,85 @@
+/// Complement of a nucleotide, including the IUPAC ambiguity codes, keeping its case.
+/// U complements to A, and anything that isn't a nucleotide (gaps included) is left alone.
+pub fn complement(c: u8) -> u8 {
+    let complemented = match c.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'R' => b'Y', //A or G
+        b'Y' => b'R', //C or T
+        b'K' => b'M', //G or T
+        b'M' => b'K', //A or C
+        b'B' => b'V', //not A
+        b'V' => b'B', //not T
+        b'D' => b'H', //not C
+        b'H' => b'D', //not G
+        other => other, //S, W and N are their own complements
+    };
+    if c.is_ascii_lowercase() {complemented.to_ascii_lowercase()} else {complemented}
+}
+
+
+/// Bases a nucleotide code stands for, as bits A = 1, C = 2, G = 4 and T = 8, or 0 if it isn't a nucleotide code
+pub fn bases(c: u8) -> u8 {
+    match c.to_ascii_uppercase() {
+        b'A' => 0b0001,
+        b'C' => 0b0010,
+        b'G' => 0b0100,
+        b'T' | b'U' => 0b1000,
+        b'R' => 0b0101,
+        b'Y' => 0b1010,
+        b'S' => 0b0110,
+        b'W' => 0b1001,
+        b'K' => 0b1100,
+        b'M' => 0b0011,
+        b'B' => 0b1110,
+        b'D' => 0b1101,
+        b'H' => 0b1011,
+        b'V' => 0b0111,
+        b'N' => 0b1111,
+        _ => 0,
+    }
+}
+
+
+/// Whether a nucleotide code stands for more than one base, N included
+pub fn is_ambiguous(c: u8) -> bool {
+    bases(c).count_ones() > 1
+}
+
+
+/// The other strand of a DNA string, read 5' to 3'
+pub fn reverse_complement(s: &str) -> String {
+    s.bytes().rev().map(|c| complement(c) as char).collect()
+}
+
+
+/// Amino acids of the standard genetic code, for codons in TCAG order (TTT, TTC, TTA, TTG, TCT, ...)
+const GENETIC_CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+
+/// Amino acid a codon codes for under the standard genetic code, '*' for stops and 'X' when it holds anything but A, C, G, T or U
+pub fn translate_codon(codon: &[u8]) -> u8 {
+    if codon.len() != 3 {
+        return b'X';
+    }
+    let mut index = 0;
+    for &c in codon {
+        let base = match c.to_ascii_uppercase() {
+            b'T' | b'U' => 0,
+            b'C' => 1,
+            b'A' => 2,
+            b'G' => 3,
+            _ => return b'X',
+        };
+        index = index * 4 + base;
+    }
+    GENETIC_CODE[index]
+}
+
+
+/// Translates a DNA string codon by codon from its first character, dropping any partial codon at the end
+pub fn translate(s: &str) -> String {
+    s.as_bytes().chunks_exact(3).map(|codon| translate_codon(codon) as char).collect()
+}
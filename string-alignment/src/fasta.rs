@@ -0,0 +1,29 @@
+/// Used to record the name of a string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedString {
+    pub name: String, //the whole header line, without the '>'
+    pub str: String,
+}
+impl NamedString {
+    pub fn name(name: &str) -> Self {
+        NamedString { name: String::from(name), str: String::new() }
+    }
+}
+
+
+/// Reads every record of a FASTA file, joining each string's lines
+pub fn read_fasta(text: &str) -> Result<Vec<NamedString>, String> {
+    let mut strings: Vec<NamedString> = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        if let Some(name) = line.strip_prefix('>') { // skip this line but push a new string
+            strings.push(NamedString::name(name.trim_end()));
+        } else {
+            match strings.last_mut() {
+                Some(string) => string.str.push_str(line.trim_end()),
+                None if line.trim().is_empty() => {},
+                None => return Err(format!("line {}: found a string before the first '>' header", num+1)),
+            }
+        }
+    }
+    Ok(strings)
+}
@@ -0,0 +1,164 @@
+use std::fmt::Write;
+use crate::dna::reverse_complement;
+use crate::structs::{Alignment, Strand};
+
+
+/// A named string as it appears in SAM and PAF, where s1 is the reference and s2 the query
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub name: &'a str, //the full FASTA header, cut to its first word on output
+    pub seq: &'a str,
+}
+impl Record<'_> {
+
+    /// SAM and PAF names can't hold whitespace, so only the first word of the header is kept
+    fn id(&self) -> &str {
+        self.name.split_whitespace().next().unwrap_or("*")
+    }
+}
+
+
+/// Extended CIGAR of an alignment, with '=' and 'X' for matches and mismatches, 'I' for characters only in the
+/// query (s2) and 'D' for characters only in the reference (s1). Unaligned ends of the query are soft clipped,
+/// and on the reverse strand the query is read along its reverse complement, as SAM expects.
+pub fn cigar(alignment: &Alignment, query_len: usize) -> String {
+    match alignment.strand {
+        Strand::Forward => clipped_cigar(alignment, alignment.s2_start, query_len - alignment.s2_end),
+        Strand::Reverse => clipped_cigar(alignment, query_len - alignment.s2_end, alignment.s2_start),
+    }
+}
+
+
+/// CIGAR of the aligned columns, with soft clips of the given lengths around them
+fn clipped_cigar(alignment: &Alignment, leading: usize, trailing: usize) -> String {
+    let mut ops: Vec<(char, usize)> = Vec::new();
+    let mut push = |op: char, count: usize| {
+        if count == 0 {
+            return;
+        }
+        match ops.last_mut() {
+            Some((last, total)) if *last == op => *total += count,
+            _ => ops.push((op, count)),
+        }
+    };
+
+    push('S', leading);
+    for (&c1, &c2) in alignment.s1_row.as_bytes().iter().zip(alignment.s2_row.as_bytes()) {
+        let op = if c1 == b'-' {
+            'I'
+        } else if c2 == b'-' {
+            'D'
+        } else if c1.eq_ignore_ascii_case(&c2) {
+            '='
+        } else {
+            'X'
+        };
+        push(op, 1);
+    }
+    push('S', trailing);
+
+    let mut out = String::new();
+    for (op, count) in ops {
+        write!(out, "{}{}", count, op).unwrap();
+    }
+    if out.is_empty() {
+        out.push('*');
+    }
+    out
+}
+
+
+/// Edit distance of the aligned part, counted the way the SAM NM tag is
+fn edit_distance(alignment: &Alignment) -> usize {
+    alignment.mismatches + alignment.gap_extensions
+}
+
+
+/// Writes the alignments as a SAM file with one header line for the reference
+pub fn to_sam(alignments: &[Alignment], reference: Record, query: Record) -> String {
+    let mut out = String::new();
+    writeln!(out, "@HD\tVN:1.6\tSO:unsorted").unwrap();
+    writeln!(out, "@SQ\tSN:{}\tLN:{}", reference.id(), reference.seq.len()).unwrap();
+    writeln!(out, "@PG\tID:{}\tPN:{}\tVN:{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")).unwrap();
+
+    let reversed = reverse_complement(query.seq);
+    for (k, alignment) in alignments.iter().enumerate() {
+        let mut flag = if k == 0 {0} else {256}; //every alignment after the first is secondary
+        let mut seq = query.seq;
+        if alignment.strand == Strand::Reverse { //SAM stores reverse strand reads as their reverse complement
+            flag |= 16;
+            seq = &reversed;
+        }
+        let position = if alignment.is_empty() {0} else {alignment.s1_start + 1};
+        writeln!(out, "{}\t{}\t{}\t{}\t255\t{}\t*\t0\t0\t{}\t*\tAS:i:{}\tNM:i:{}",
+            query.id(), flag, reference.id(), position, cigar(alignment, query.seq.len()),
+            if seq.is_empty() {"*"} else {seq}, alignment.score, edit_distance(alignment)).unwrap();
+    }
+    out
+}
+
+
+/// Writes the alignments as PAF lines, with the score and the CIGAR of the aligned part as tags
+pub fn to_paf(alignments: &[Alignment], reference: Record, query: Record) -> String {
+    let mut out = String::new();
+    for (k, alignment) in alignments.iter().enumerate() {
+        let kind = if k == 0 {'P'} else {'S'}; //primary or secondary
+        let strand = if alignment.strand == Strand::Reverse {'-'} else {'+'};
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t255\ttp:A:{}\tAS:i:{}\tNM:i:{}\tcg:Z:{}",
+            query.id(), query.seq.len(), alignment.s2_start, alignment.s2_end, strand,
+            reference.id(), reference.seq.len(), alignment.s1_start, alignment.s1_end,
+            alignment.matches, alignment.len(), kind, alignment.score, edit_distance(alignment),
+            clipped_cigar(alignment, 0, 0)).unwrap();
+    }
+    out
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::smith_waterman;
+    use crate::testing::config;
+    use crate::structs::AlignmentKind;
+
+    #[test]
+    fn cigar_clips_unaligned_query_ends() {
+        let alignment = Alignment::from_rows(AlignmentKind::Local, String::from("AC-GTA"), String::from("ACTG-A"), 3, 1, 0, &config(""));
+        assert_eq!(cigar(&alignment, 8), "1S2=1I1=1D1=2S");
+        assert_eq!(clipped_cigar(&alignment, 0, 0), "2=1I1=1D1=");
+
+        let alignment = Alignment::from_rows(AlignmentKind::Global, String::from("ACGT"), String::from("AGGT"), 0, 0, 0, &config(""));
+        assert_eq!(cigar(&alignment, 4), "1=1X2=");
+        let empty = Alignment::from_rows(AlignmentKind::Local, String::new(), String::new(), 0, 0, 0, &config(""));
+        assert_eq!(cigar(&empty, 0), "*");
+        assert_eq!(cigar(&empty, 3), "3S");
+    }
+
+    #[test]
+    fn cigar_reads_reverse_strand_along_the_reverse_complement() {
+        let alignment = Alignment::from_rows(AlignmentKind::Local, String::from("AC-GTA"), String::from("ACTG-A"), 3, 1, 0, &config("")).on_reverse_strand(8);
+        assert_eq!(cigar(&alignment, 8), "1S2=1I1=1D1=2S");
+    }
+
+    #[test]
+    fn sam_and_paf_for_both_strands() {
+        let config = config("");
+        let reference = Record { name: "ref description", seq: "GGGACGTACGTTTT" };
+
+        let query = Record { name: "fwd", seq: "CACGTACGAAA" };
+        let alignment = smith_waterman(reference.seq, query.seq, &config);
+        assert_eq!((alignment.s2_start, alignment.s2_end), (1, 8));
+        let sam = to_sam(std::slice::from_ref(&alignment), reference, query);
+        assert_eq!(sam.lines().nth(1), Some("@SQ\tSN:ref\tLN:14"));
+        assert_eq!(sam.lines().nth(3), Some("fwd\t0\tref\t4\t255\t1S7=3S\t*\t0\t0\tCACGTACGAAA\t*\tAS:i:7\tNM:i:0"));
+        assert_eq!(to_paf(&[alignment], reference, query), "fwd\t11\t1\t8\t+\tref\t14\t3\t10\t7\t7\t255\ttp:A:P\tAS:i:7\tNM:i:0\tcg:Z:7=\n");
+
+        let query = Record { name: "rev", seq: "TTTCGTACGTG" }; //the reverse complement of the forward query
+        let alignment = smith_waterman(reference.seq, &reverse_complement(query.seq), &config).on_reverse_strand(query.seq.len());
+        assert_eq!((alignment.s2_start, alignment.s2_end), (3, 10));
+        let sam = to_sam(std::slice::from_ref(&alignment), reference, query);
+        assert_eq!(sam.lines().nth(3), Some("rev\t16\tref\t4\t255\t1S7=3S\t*\t0\t0\tCACGTACGAAA\t*\tAS:i:7\tNM:i:0"));
+        assert_eq!(to_paf(&[alignment], reference, query), "rev\t11\t3\t10\t-\tref\t14\t3\t10\t7\t7\t255\ttp:A:P\tAS:i:7\tNM:i:0\tcg:Z:7=\n");
+    }
+}
@@ -0,0 +1,450 @@
+use crate::algorithms::{needleman_wunsch, smith_waterman, State};
+use crate::structs::{Alignment, AlignmentKind, Config, Matrix, REAL_MIN};
+
+
+/// Longest gap in each length class of the report, with one more class for anything longer
+pub const GAP_CLASSES: [usize; 4] = [1, 5, 20, 100];
+
+/// Longest the two strings can be together under the log gap model, whose fill takes time cubic in their lengths
+pub const LOG_MAX_LENGTH: usize = 2000;
+
+/// Marks a pair of characters that starts a local alignment
+const START: u8 = u8::MAX;
+
+/// States of the two-piece fill, in the order ties are broken
+const S: usize = 0;
+const D: usize = 1; //s1 against a gap, on the short-gap line
+const I: usize = 2; //s2 against a gap, on the short-gap line
+const D_LONG: usize = 3; //s1 against a gap, on the long-gap line
+const I_LONG: usize = 4;
+
+
+/// How the score of a gap grows with its length k
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapModel {
+    Affine, //h + k*g
+    TwoPiece { h1: i32, g1: i32, h2: i32, g2: i32 }, //the better of h + k*g and a second line for gaps in s1 or s2, with a costlier open and a cheaper extend for long gaps
+    Log, //h + g*(1 + ln k), the same as affine for one character but with each further one costing less
+}
+impl GapModel {
+
+    /// Parses "affine", "two-piece" or "log". Two-piece needs the open and extend penalties of its long-gap lines, for gaps in s1 then s2.
+    pub fn parse(name: &str, long: Option<((i32, i32), (i32, i32))>) -> Result<Self, String> {
+        match (name, long) {
+            ("affine", _) => Ok(GapModel::Affine),
+            ("two-piece", Some(((h1, g1), (h2, g2)))) => Ok(GapModel::TwoPiece { h1, g1, h2, g2 }),
+            ("two-piece", None) => Err(String::from("the two-piece gap model needs h-long and g-long, or h1-long, g1-long, h2-long and g2-long")),
+            ("log", _) => Ok(GapModel::Log),
+            _ => Err(format!("unknown gap model \"{}\", expected affine, two-piece or log", name)),
+        }
+    }
+
+    /// Name to show in the output
+    pub fn name(&self) -> &'static str {
+        match self {
+            GapModel::Affine => "affine",
+            GapModel::TwoPiece { .. } => "two-piece",
+            GapModel::Log => "log",
+        }
+    }
+
+    /// Open and extend penalties of the long-gap line for gaps in s1, or in s2 with `in_s2`
+    pub fn long_line(&self, in_s2: bool) -> Option<(i32, i32)> {
+        match *self {
+            GapModel::TwoPiece { h1, g1, .. } if !in_s2 => Some((h1, g1)),
+            GapModel::TwoPiece { h2, g2, .. } => Some((h2, g2)),
+            _ => None,
+        }
+    }
+
+    /// Score of a gap of k characters in s1, or in s2 with `in_s2`, given the open and extend penalties of that string
+    #[inline]
+    pub fn score(&self, h: i32, g: i32, k: usize, in_s2: bool) -> i32 {
+        if k == 0 {
+            return 0;
+        }
+        match *self {
+            GapModel::Affine => h + g * k as i32,
+            GapModel::TwoPiece { .. } => {
+                let (long_h, long_g) = self.long_line(in_s2).unwrap();
+                (h + g * k as i32).max(long_h + long_g * k as i32)
+            },
+            GapModel::Log => h + (g as f64 * (1.0 + (k as f64).ln())).round() as i32,
+        }
+    }
+}
+
+
+/// Aligns globally or locally under the config's gap model
+pub fn gap_model_align(s1: &str, s2: &str, config: &Config, local: bool) -> Alignment {
+    match config.gap_model {
+        GapModel::Affine if local => smith_waterman(s1, s2, config),
+        GapModel::Affine => needleman_wunsch(s1, s2, config),
+        GapModel::TwoPiece { .. } => two_piece(s1, s2, config, local),
+        GapModel::Log => {
+            if s1.len() + s2.len() > LOG_MAX_LENGTH {
+                panic!("The log gap model takes time cubic in the lengths of the strings, so they can be {} characters long together at most, not {}",
+                    LOG_MAX_LENGTH, s1.len() + s2.len());
+            }
+            log_gaps(s1, s2, config, local)
+        },
+    }
+}
+
+
+/// First of the given states with the best score, so earlier states win ties
+fn best_of(scores: &[i32], states: &[usize]) -> usize {
+    let mut best = states[0];
+    for &state in &states[1..] {
+        if scores[state] > scores[best] {
+            best = state;
+        }
+    }
+    best
+}
+
+
+/// Two-piece affine alignment, as in minimap2. Each gap follows either the short-gap line h + k*g or the long-gap line
+/// of its string, whichever scores better, so the fill keeps a pair of gap states per string: five states in all.
+fn two_piece(s1: &str, s2: &str, config: &Config, local: bool) -> Alignment {
+    let (long_h1, long_g1) = config.gap_model.long_line(false).expect("two_piece is only run under the two-piece gap model");
+    let (long_h2, long_g2) = config.gap_model.long_line(true).unwrap();
+    let n = s1.len();
+    let m = s2.len();
+    let mut cells: Matrix<[i32; 5]> = Matrix::with_shape(n+1, m+1);
+    let mut trace: Matrix<[u8; 5]> = Matrix::with_shape(n+1, m+1); //the state each state was reached from
+    let all = [S, D, I, D_LONG, I_LONG];
+
+    let (mut top_i, mut top_j, mut top_score) = (0, 0, 0);
+    for i in 0..n+1 {
+        for j in 0..m+1 {
+            let mut cell = [REAL_MIN; 5];
+            let mut from = [0u8; 5];
+            if i == 0 && j == 0 && !local { //setup corner, the edges then follow from it
+                cell[S] = 0;
+            }
+
+            // a pair of characters, continuing the best path into the diagonal or, locally, starting afresh
+            if i > 0 && j > 0 {
+                let diag = cells.index(i-1, j-1);
+                let state = best_of(diag, &all);
+                let pair = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+                if local && diag[state] <= 0 {
+                    cell[S] = pair;
+                    from[S] = START;
+                } else {
+                    cell[S] = diag[state] + pair;
+                    from[S] = state as u8;
+                }
+            }
+
+            // gaps extend along their own line or open from any state but the other line of the same string
+            if i > 0 {
+                let up = cells.index(i-1, j);
+                let open = best_of(up, &[S, I, I_LONG]);
+                for (state, h, g) in [(D, config.h2, config.g2), (D_LONG, long_h2, long_g2)] {
+                    (cell[state], from[state]) = if up[state] >= up[open] + h {
+                        (up[state] + g, state as u8)
+                    } else {
+                        (up[open] + h + g, open as u8)
+                    };
+                }
+            }
+            if j > 0 {
+                let left = cells.index(i, j-1);
+                let open = best_of(left, &[S, D, D_LONG]);
+                for (state, h, g) in [(I, config.h1, config.g1), (I_LONG, long_h1, long_g1)] {
+                    (cell[state], from[state]) = if left[state] >= left[open] + h {
+                        (left[state] + g, state as u8)
+                    } else {
+                        (left[open] + h + g, open as u8)
+                    };
+                }
+            }
+
+            // local alignments end on a pair
+            if local && cell[S] > top_score {
+                top_score = cell[S];
+                top_i = i;
+                top_j = j;
+            }
+            *cells.index_mut(i, j) = cell;
+            *trace.index_mut(i, j) = from;
+        }
+    }
+
+    let (end_i, end_j, mut state, score) = if local {
+        if top_score == 0 {
+            return Alignment::from_rows(AlignmentKind::Local, String::new(), String::new(), 0, 0, 0, config);
+        }
+        (top_i, top_j, S, top_score)
+    } else {
+        let last = cells.index(n, m);
+        let state = best_of(last, &all);
+        (n, m, state, last[state])
+    };
+
+    // follow the states back to the corner, or to the pair that started a local alignment
+    let mut path: Vec<State> = Vec::with_capacity(end_i + end_j);
+    let (mut i, mut j) = (end_i, end_j);
+    while i != 0 || j != 0 {
+        let from = trace.index(i, j)[state];
+        match state {
+            S => {
+                path.push(State::S);
+                i -= 1;
+                j -= 1;
+            },
+            D | D_LONG => {
+                path.push(State::D);
+                i -= 1;
+            },
+            _ => {
+                path.push(State::I);
+                j -= 1;
+            },
+        }
+        if from == START {
+            break;
+        }
+        state = from as usize;
+    }
+    path.reverse();
+    let (s1_row, s2_row) = lay_out(s1, s2, &path, i, j);
+    let kind = if local {AlignmentKind::Local} else {AlignmentKind::Global};
+    Alignment::from_rows(kind, s1_row, s2_row, i, j, score, config)
+}
+
+
+/// How each state of a cell under the log gap model was reached
+#[derive(Debug, Clone, Default)]
+struct LogTrace {
+    from: [u8; 3], //previous state of S, D and I
+    d_len: usize, //length of the gap ending in D
+    i_len: usize,
+}
+
+
+/// Alignment under the log gap model (Waterman-Smith-Beyer). A gap's score isn't a running sum, so each gap state looks back
+/// over every length of gap that could end there, which makes the fill cubic rather than quadratic.
+fn log_gaps(s1: &str, s2: &str, config: &Config, local: bool) -> Alignment {
+    let n = s1.len();
+    let m = s2.len();
+    let s1_gaps: Vec<i32> = (0..m+1).map(|k| config.s1_gap(k)).collect(); //score of each length of gap, worked out once
+    let s2_gaps: Vec<i32> = (0..n+1).map(|k| config.s2_gap(k)).collect();
+    let mut cells: Matrix<[i32; 3]> = Matrix::with_shape(n+1, m+1); //S, D and I
+    let mut trace: Matrix<LogTrace> = Matrix::with_shape(n+1, m+1);
+    let all = [S, D, I];
+
+    let (mut top_i, mut top_j, mut top_score) = (0, 0, 0);
+    for i in 0..n+1 {
+        for j in 0..m+1 {
+            let mut cell = [REAL_MIN; 3];
+            let mut step = LogTrace::default();
+            if i == 0 && j == 0 && !local { //setup corner, the edges then follow from it
+                cell[S] = 0;
+            }
+
+            if i > 0 && j > 0 {
+                let diag = cells.index(i-1, j-1);
+                let state = best_of(diag, &all);
+                let pair = config.substitution(s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+                if local && diag[state] <= 0 {
+                    cell[S] = pair;
+                    step.from[S] = START;
+                } else {
+                    cell[S] = diag[state] + pair;
+                    step.from[S] = state as u8;
+                }
+            }
+
+            // a gap of every length ending here, after anything but another gap in the same string
+            for (k, &gap) in s2_gaps.iter().enumerate().take(i+1).skip(1) {
+                let up = cells.index(i-k, j);
+                let state = best_of(up, &[S, I]);
+                if up[state] + gap > cell[D] {
+                    cell[D] = up[state] + gap;
+                    step.from[D] = state as u8;
+                    step.d_len = k;
+                }
+            }
+            for (k, &gap) in s1_gaps.iter().enumerate().take(j+1).skip(1) {
+                let left = cells.index(i, j-k);
+                let state = best_of(left, &[S, D]);
+                if left[state] + gap > cell[I] {
+                    cell[I] = left[state] + gap;
+                    step.from[I] = state as u8;
+                    step.i_len = k;
+                }
+            }
+
+            if local && cell[S] > top_score {
+                top_score = cell[S];
+                top_i = i;
+                top_j = j;
+            }
+            *cells.index_mut(i, j) = cell;
+            *trace.index_mut(i, j) = step;
+        }
+    }
+
+    let (end_i, end_j, mut state, score) = if local {
+        if top_score == 0 {
+            return Alignment::from_rows(AlignmentKind::Local, String::new(), String::new(), 0, 0, 0, config);
+        }
+        (top_i, top_j, S, top_score)
+    } else {
+        let last = cells.index(n, m);
+        let state = best_of(last, &all);
+        (n, m, state, last[state])
+    };
+
+    // follow the states back, a whole gap at a time
+    let mut path: Vec<State> = Vec::with_capacity(end_i + end_j);
+    let (mut i, mut j) = (end_i, end_j);
+    while i != 0 || j != 0 {
+        let step = trace.index(i, j);
+        match state {
+            S => {
+                path.push(State::S);
+                i -= 1;
+                j -= 1;
+            },
+            D => {
+                path.extend(std::iter::repeat_n(State::D, step.d_len));
+                i -= step.d_len;
+            },
+            _ => {
+                path.extend(std::iter::repeat_n(State::I, step.i_len));
+                j -= step.i_len;
+            },
+        }
+        if step.from[state] == START {
+            break;
+        }
+        state = step.from[state] as usize;
+    }
+    path.reverse();
+    let (s1_row, s2_row) = lay_out(s1, s2, &path, i, j);
+    let kind = if local {AlignmentKind::Local} else {AlignmentKind::Global};
+    Alignment::from_rows(kind, s1_row, s2_row, i, j, score, config)
+}
+
+
+/// Gapped rows of a path that starts at s1[i], s2[j]
+fn lay_out(s1: &str, s2: &str, path: &[State], mut i: usize, mut j: usize) -> (String, String) {
+    let mut s1_row = String::with_capacity(path.len());
+    let mut s2_row = String::with_capacity(path.len());
+    for state in path {
+        match state {
+            State::S => {
+                s1_row.push(s1.as_bytes()[i] as char);
+                s2_row.push(s2.as_bytes()[j] as char);
+                i += 1;
+                j += 1;
+            },
+            State::D => {
+                s1_row.push(s1.as_bytes()[i] as char);
+                s2_row.push('-');
+                i += 1;
+            },
+            State::I => {
+                s1_row.push('-');
+                s2_row.push(s2.as_bytes()[j] as char);
+                j += 1;
+            },
+        }
+    }
+    (s1_row, s2_row)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::testing::{config, random_dna};
+
+    /// Score of a pair of gapped rows, each gap scored whole by the gap model
+    fn rescore(s1_row: &str, s2_row: &str, config: &Config) -> i32 {
+        let (s1_row, s2_row) = (s1_row.as_bytes(), s2_row.as_bytes());
+        let mut score = 0;
+        let mut k = 0;
+        while k < s1_row.len() {
+            if s1_row[k] != b'-' && s2_row[k] != b'-' {
+                score += config.substitution(s1_row[k], s2_row[k]);
+                k += 1;
+                continue;
+            }
+            let in_s1 = s1_row[k] == b'-';
+            let start = k;
+            while k < s1_row.len() && (s1_row[k] == b'-') == in_s1 && (s2_row[k] == b'-') != in_s1 {
+                k += 1;
+            }
+            score += if in_s1 {config.s1_gap(k - start)} else {config.s2_gap(k - start)};
+        }
+        score
+    }
+
+    /// Best score of any global alignment, trying every one
+    fn brute_force(s1: &[u8], s2: &[u8], config: &Config, s1_row: &mut String, s2_row: &mut String) -> i32 {
+        if s1.is_empty() && s2.is_empty() {
+            return rescore(s1_row, s2_row, config);
+        }
+        let mut best = i32::MIN;
+        for (k1, k2) in [(1, 1), (1, 0), (0, 1)] {
+            if s1.len() < k1 || s2.len() < k2 {
+                continue;
+            }
+            s1_row.push(if k1 == 1 {s1[0] as char} else {'-'});
+            s2_row.push(if k2 == 1 {s2[0] as char} else {'-'});
+            best = best.max(brute_force(&s1[k1..], &s2[k2..], config, s1_row, s2_row));
+            s1_row.pop();
+            s2_row.pop();
+        }
+        best
+    }
+
+    fn check(text: &str, model: &str) {
+        let config = config(text);
+        assert_eq!(config.gap_model.name(), model);
+        let mut rng = Rng::new(9);
+        for _ in 0..200 {
+            let s1 = random_dna(&mut rng, 7);
+            let s2 = random_dna(&mut rng, 7);
+            let global = gap_model_align(&s1, &s2, &config, false);
+            assert_eq!(global.s1_row.replace('-', ""), s1);
+            assert_eq!(global.s2_row.replace('-', ""), s2);
+            assert_eq!(rescore(&global.s1_row, &global.s2_row, &config), global.score, "{} vs {}", s1, s2);
+            assert_eq!(global.score, brute_force(s1.as_bytes(), s2.as_bytes(), &config, &mut String::new(), &mut String::new()), "{} vs {}", s1, s2);
+
+            let local = gap_model_align(&s1, &s2, &config, true);
+            assert_eq!(rescore(&local.s1_row, &local.s2_row, &config), local.score, "{} vs {}", s1, s2);
+            assert!(local.score >= global.score.max(0));
+        }
+    }
+
+    #[test]
+    fn two_piece_alignments_rescore() {
+        check("match = 2\nmismatch = -4\ngap-model = two-piece\nh = -4\ng = -2\nh-long = -9\ng-long = -1\n", "two-piece");
+        check("match = 2\nmismatch = -4\ngap-model = two-piece\nh1 = -2\ng1 = -3\nh2 = -4\ng2 = -2\nh1-long = -6\ng1-long = 0\nh2-long = -12\ng2-long = -1\n", "two-piece");
+    }
+
+    #[test]
+    fn log_alignments_rescore() {
+        check("match = 2\nmismatch = -3\ngap-model = log\nh = -3\ng = -2\n", "log");
+        check("match = 2\nmismatch = -3\ngap-model = log\nh1 = -1\ng1 = -4\nh2 = -5\ng2 = -1\n", "log");
+    }
+
+    #[test]
+    fn gap_scores() {
+        let two_piece = GapModel::TwoPiece { h1: -10, g1: -1, h2: -20, g2: 0 };
+        assert_eq!(two_piece.score(-2, -3, 0, false), 0);
+        assert_eq!(two_piece.score(-2, -3, 2, false), -8);
+        assert_eq!(two_piece.score(-2, -3, 10, false), -20);
+        assert_eq!(two_piece.score(-2, -3, 10, true), -20);
+        assert_eq!(two_piece.score(-2, -3, 20, true), -20);
+        assert_eq!(GapModel::Log.score(-3, -2, 1, false), -5);
+        assert_eq!(GapModel::Log.score(-3, -2, 10, true), -3 + (-2.0 * (1.0 + 10f64.ln())).round() as i32);
+    }
+}
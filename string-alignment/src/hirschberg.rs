@@ -0,0 +1,244 @@
+use crate::algorithms::score_rows;
+use crate::structs::{Alignment, AlignmentKind, Config};
+
+
+/// Implements Myers-Miller (Hirschberg's divide and conquer with affine gaps) for global alignment in linear space
+pub fn myers_miller(s1: &str, s2: &str, config: &Config) -> Alignment {
+    let mut aligner = MyersMiller::new(s1, s2, config);
+    aligner.diff(0, 0, s1.len(), s2.len(), aligner.del_open, aligner.del_open);
+
+    let score = score_rows(&aligner.s1_str, &aligner.s2_str, config);
+    Alignment::from_rows(AlignmentKind::Global, aligner.s1_str, aligner.s2_str, 0, 0, score, config)
+}
+
+
+/// Working state for one Myers-Miller alignment.
+/// Internally everything is a non-negative cost, so a gap of length k costs open + k*extend.
+/// Deletions are characters of s1 against gaps in s2, insertions characters of s2 against gaps in s1.
+struct MyersMiller<'a> {
+    s1: &'a [u8],
+    s2: &'a [u8],
+    config: &'a Config,
+    del_open: i32, //-h2
+    del_extend: i32, //-g2
+    ins_open: i32, //-h1
+    ins_extend: i32, //-g1
+    cc: Vec<i32>, //forward costs
+    dd: Vec<i32>, //forward costs ending in a deletion
+    rr: Vec<i32>, //reverse costs
+    ss: Vec<i32>, //reverse costs starting with a deletion
+    s1_str: String,
+    s2_str: String,
+}
+impl<'a> MyersMiller<'a> {
+    fn new(s1: &'a str, s2: &'a str, config: &'a Config) -> Self {
+        MyersMiller {
+            s1: s1.as_bytes(),
+            s2: s2.as_bytes(),
+            config,
+            del_open: -config.h2,
+            del_extend: -config.g2,
+            ins_open: -config.h1,
+            ins_extend: -config.g1,
+            cc: vec![0; s2.len()+1],
+            dd: vec![0; s2.len()+1],
+            rr: vec![0; s2.len()+1],
+            ss: vec![0; s2.len()+1],
+            s1_str: String::with_capacity(s1.len() + s2.len()),
+            s2_str: String::with_capacity(s1.len() + s2.len()),
+        }
+    }
+
+    /// Cost of inserting k characters of s2
+    fn gap(&self, k: usize) -> i32 {
+        if k == 0 {
+            0
+        } else {
+            self.ins_open + self.ins_extend * k as i32
+        }
+    }
+
+    /// Cost of aligning two characters
+    fn weight(&self, c1: u8, c2: u8) -> i32 {
+        -self.config.substitution(c1, c2)
+    }
+
+    /// Deletes k characters of s1 starting at a
+    fn del(&mut self, a: usize, k: usize) {
+        for &c in &self.s1[a..a+k] {
+            self.s1_str.push(c as char);
+            self.s2_str.push('-');
+        }
+    }
+
+    /// Inserts k characters of s2 starting at b
+    fn ins(&mut self, b: usize, k: usize) {
+        for &c in &self.s2[b..b+k] {
+            self.s1_str.push('-');
+            self.s2_str.push(c as char);
+        }
+    }
+
+    /// Aligns s1[a..a+m] against s2[b..b+n], appending to the rows.
+    /// tb and te are what opening a deletion costs at the start and end, which is 0 when it continues a neighbouring deletion.
+    fn diff(&mut self, a: usize, b: usize, m: usize, n: usize, tb: i32, te: i32) {
+        let (g, h) = (self.del_open, self.del_extend);
+        let (ig, ih) = (self.ins_open, self.ins_extend);
+
+        // base cases
+        if n == 0 {
+            self.del(a, m);
+            return;
+        }
+        if m == 0 {
+            self.ins(b, n);
+            return;
+        }
+        if m == 1 { //either delete the one character or line it up with one of s2's
+            let mut midc = tb.min(te) + h + self.gap(n);
+            let mut midj = 0;
+            for j in 1..n+1 {
+                let c = self.gap(j-1) + self.weight(self.s1[a], self.s2[b+j-1]) + self.gap(n-j);
+                if c < midc {
+                    midc = c;
+                    midj = j;
+                }
+            }
+            if midj == 0 { //put the deletion next to whichever neighbour it continues
+                if tb <= te {
+                    self.del(a, 1);
+                    self.ins(b, n);
+                } else {
+                    self.ins(b, n);
+                    self.del(a, 1);
+                }
+            } else {
+                self.ins(b, midj-1);
+                self.s1_str.push(self.s1[a] as char);
+                self.s2_str.push(self.s2[b+midj-1] as char);
+                self.ins(b+midj, n-midj);
+            }
+            return;
+        }
+
+        // forward pass over the top half
+        let midi = m / 2;
+        self.cc[0] = 0;
+        let mut t = ig;
+        for j in 1..n+1 {
+            t += ih;
+            self.cc[j] = t;
+            self.dd[j] = t + g;
+        }
+        t = tb;
+        for i in 1..midi+1 {
+            let mut s = self.cc[0];
+            t += h;
+            let mut c = t;
+            self.cc[0] = c;
+            let mut e = t + ig;
+            for j in 1..n+1 {
+                e = (e + ih).min(c + ig + ih);
+                let d = (self.dd[j] + h).min(self.cc[j] + g + h);
+                c = (s + self.weight(self.s1[a+i-1], self.s2[b+j-1])).min(d).min(e);
+                s = self.cc[j];
+                self.cc[j] = c;
+                self.dd[j] = d;
+            }
+        }
+        self.dd[0] = self.cc[0];
+
+        // reverse pass over the bottom half
+        self.rr[n] = 0;
+        t = ig;
+        for j in (0..n).rev() {
+            t += ih;
+            self.rr[j] = t;
+            self.ss[j] = t + g;
+        }
+        t = te;
+        for i in (midi..m).rev() {
+            let mut s = self.rr[n];
+            t += h;
+            let mut c = t;
+            self.rr[n] = c;
+            let mut e = t + ig;
+            for j in (0..n).rev() {
+                e = (e + ih).min(c + ig + ih);
+                let d = (self.ss[j] + h).min(self.rr[j] + g + h);
+                c = (s + self.weight(self.s1[a+i], self.s2[b+j])).min(d).min(e);
+                s = self.rr[j];
+                self.rr[j] = c;
+                self.ss[j] = d;
+            }
+        }
+        self.ss[n] = self.rr[n];
+
+        // find where the optimal path crosses the middle row, either between two cells or inside a deletion
+        let mut midc = self.cc[0] + self.rr[0];
+        let mut midj = 0;
+        let mut through_gap = false;
+        for j in 0..n+1 {
+            let c = self.cc[j] + self.rr[j];
+            if c < midc {
+                midc = c;
+                midj = j;
+            }
+        }
+        for j in (0..n+1).rev() {
+            let c = self.dd[j] + self.ss[j] - g;
+            if c < midc {
+                midc = c;
+                midj = j;
+                through_gap = true;
+            }
+        }
+
+        // conquer both halves
+        if through_gap {
+            self.diff(a, b, midi-1, midj, tb, 0);
+            self.del(a+midi-1, 2);
+            self.diff(a+midi+1, b+midj, m-midi-1, n-midj, 0, te);
+        } else {
+            self.diff(a, b, midi, midj, tb, g);
+            self.diff(a+midi, b+midj, m-midi, n-midj, g, te);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::needleman_wunsch;
+    use crate::random::Rng;
+    use crate::testing::{config, random_dna};
+
+    fn check(s1: &str, s2: &str, config: &Config) {
+        let alignment = myers_miller(s1, s2, config);
+        assert_eq!(alignment.score, needleman_wunsch(s1, s2, config).score, "{} vs {}", s1, s2);
+        assert_eq!(alignment.s1_row.replace('-', ""), s1);
+        assert_eq!(alignment.s2_row.replace('-', ""), s2);
+    }
+
+    #[test]
+    fn matches_needleman_wunsch() {
+        let settings = [
+            "match = 1\nmismatch = -1\nh = -5\ng = -1\n",
+            "match = 2\nmismatch = -3\nh1 = -5\ng1 = 0\nh2 = -4\ng2 = -1\n",
+            "match = 1\nmismatch = -2\nh1 = -1\ng1 = -3\nh2 = -8\ng2 = -1\n",
+        ];
+        let mut rng = Rng::new(3);
+        for text in settings {
+            let config = config(text);
+            check("", "", &config);
+            check("ACGT", "", &config);
+            check("", "ACGT", &config);
+            for _ in 0..300 {
+                let s1 = random_dna(&mut rng, 40);
+                let s2 = random_dna(&mut rng, 40);
+                check(&s1, &s2, &config);
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Pairwise and multiple string alignment with affine gaps.
+//!
+//! Build a [`Config`] (directly or from a config file with [`config::ConfigFile`]), read strings with
+//! [`fasta::read_fasta`], run one of the aligners and render the resulting [`Alignment`].
+
+pub mod algorithms;
+pub mod banded;
+pub mod bigint;
+pub mod codon;
+pub mod config;
+pub mod cooptimal;
+pub mod dna;
+pub mod fasta;
+pub mod formats;
+pub mod gaps;
+pub mod hirschberg;
+pub mod mask;
+pub mod matrices;
+pub mod msa;
+pub mod pairwise;
+pub mod phylogeny;
+pub mod random;
+pub mod report;
+pub mod stats;
+pub mod striped;
+pub mod structs;
+#[cfg(test)]
+mod testing;
+pub mod translated;
+pub mod wavefront;
+
+pub use algorithms::{end_gap_free, needleman_wunsch, optimal_score, smith_waterman, waterman_eggert};
+pub use banded::{banded, BandReport};
+pub use codon::{codon_align, CodonReport};
+pub use config::{ConfigError, ConfigFile};
+pub use cooptimal::CoOptimal;
+pub use dna::reverse_complement;
+pub use fasta::{read_fasta, NamedString};
+pub use gaps::{gap_model_align, GapModel};
+pub use hirschberg::myers_miller;
+pub use matrices::SubstitutionMatrix;
+pub use report::{render_alignment, render_report};
+pub use striped::striped_smith_waterman;
+pub use structs::{Alignment, AlignmentKind, Config, EndGaps, Strand};
+pub use translated::{translated_align, TranslatedAlignment};
@@ -1,22 +1,27 @@
-mod algorithms;
-mod structs;
-
-use std::fs;
+use std::{fs, process};
 use clap::{arg, command};
-use algorithms::{needleman_wunsch, smith_waterman};
-use structs::Config;
-
-/// Used to record the name of a string
-#[derive(Debug)]
-struct NamedString {
-    name: String,
-    str: String,
-}
-impl NamedString {
-    fn name(name: &str) -> Self {
-        NamedString { name: String::from(name), str: String::new() }
-    }
-}
+use comp_gen::algorithms::{end_gap_free, needleman_wunsch, optimal_score, smith_waterman, waterman_eggert};
+use comp_gen::banded::{banded, BandReport};
+use comp_gen::codon::codon_align;
+use comp_gen::config::{parse_gap_track, ConfigError, ConfigFile, DEFAULT_PROFILE};
+use comp_gen::cooptimal::CoOptimal;
+use comp_gen::dna::reverse_complement;
+use comp_gen::fasta::read_fasta;
+use comp_gen::formats::{to_paf, to_sam, Record};
+use comp_gen::gaps::{gap_model_align, GapModel};
+use comp_gen::hirschberg::myers_miller;
+use comp_gen::mask::{soft_mask, Masker};
+use comp_gen::matrices::IUPAC;
+use comp_gen::msa::{guide_distances, progressive, to_aligned_fasta, to_clustal};
+use comp_gen::pairwise::{all_vs_all, from_phylip, to_phylip, to_tsv};
+use comp_gen::phylogeny::{neighbor_joining, upgma, Tree};
+use comp_gen::random::{Rng, Shuffle, DEFAULT_SEED};
+use comp_gen::report::{render_alignment, render_codon_report, render_parameters, render_shuffle_test, render_significance, render_translated};
+use comp_gen::stats::{KarlinAltschul, ShuffleTest};
+use comp_gen::striped::{striped_smith_waterman, Backend};
+use comp_gen::structs::{Alignment, AlignmentKind, Cell, DistanceMatrix, EndGaps};
+use comp_gen::translated::translated_align;
+use comp_gen::wavefront::{wavefront_needleman_wunsch, wavefront_smith_waterman};
 
 
 /// Reads a file and returns it as a string
@@ -25,6 +30,16 @@ fn read_file(file: &str) -> String {
 }
 
 
+/// Builds a tree from the distances with the named method
+fn build_tree(method: &str, distances: &DistanceMatrix) -> Tree {
+    match method {
+        "nj" => neighbor_joining(distances),
+        "upgma" => upgma(distances),
+        _ => panic!("Unknown tree method {}, expected nj or upgma", method),
+    }
+}
+
+
 /// Main entry point
 fn main() {
 
@@ -34,104 +49,517 @@ fn main() {
             [FILE] "File containing the strings to compare"
         ))
         .arg(arg!(
-            [ALG] "0: Global (Needleman-Wunsch), 1: Local (Smith-Waterman)"
+            [ALG] "0: Global (Needleman-Wunsch), 1: Local (Smith-Waterman), 2: Semi-global, 3: Overlap, 4: Glocal"
         ))
         .arg(arg!(
             [CONFIG] "Path to custom config file"
         ))
+        .arg(arg!(
+            --profile <NAME> "Scoring profile to use from the config file, the settings outside any [profile] by default"
+        ))
+        .arg(arg!(
+            --linear "Use linear-space global alignment (Myers-Miller) regardless of size"
+        ))
+        .arg(arg!(
+            --memory <MB> "Largest DP matrix to allocate for global alignment before switching to linear space, and for joining --msa profiles or counting co-optimal alignments before giving up"
+        ).default_value("1024"))
+        .arg(arg!(
+            --"free-ends" <ENDS> "Comma separated ends whose gaps are free for ALG 2-4, from s1_start, s1_end, s2_start, s2_end"
+        ))
+        .arg(arg!(
+            --"score-only" "Only compute the optimal global or local score, in linear space"
+        ))
+        .arg(arg!(
+            --"all-pairs" <PREFIX> "Align every pair of strings in FILE, writing score, identity and distance matrices to PREFIX.*"
+        ))
+        .arg(arg!(
+            --threads <N> "Number of threads to align with, spread over the pairs for --all-pairs and --msa or over the matrix otherwise"
+        ).default_value("1"))
+        .arg(arg!(
+            --band <W> "Only fill cells within W diagonals of the main diagonal"
+        ))
+        .arg(arg!(
+            --widen "Double the band and retry until the banded alignment is provably optimal"
+        ))
+        .arg(arg!(
+            --tree <METHOD> "Build a tree from the pairwise distances with nj (neighbor-joining) or upgma, written as Newick"
+        ))
+        .arg(arg!(
+            --distances <PHYLIP> "Build the tree from this PHYLIP distance matrix (such as the one --all-pairs writes) instead of aligning FILE"
+        ))
+        .arg(arg!(
+            --msa <PREFIX> "Progressively align all the strings in FILE, writing PREFIX.aln (Clustal) and PREFIX.fasta"
+        ))
+        .arg(arg!(
+            --top <K> "Report the K best local alignments that share no aligned pairs, for ALG 1"
+        ))
+        .arg(arg!(
+            --"min-score" <S> "Lowest score a local alignment reported by --top may have"
+        ).default_value("1"))
+        .arg(arg!(
+            --sam <OUT> "Also write the alignment as SAM, with s1 as the reference and s2 as the query"
+        ))
+        .arg(arg!(
+            --paf <OUT> "Also write the alignment as PAF, with s1 as the target and s2 as the query"
+        ))
+        .arg(arg!(
+            --shuffles <N> "Judge local alignments by lambda and K fitted to N shuffles of s2 aligned with the full gapped scoring"
+        ).default_value("100"))
+        .arg(arg!(
+            --ungapped "Judge local alignments by the exact ungapped lambda and K instead, which ignore gaps and so overstate significance"
+        ))
+        .arg(arg!(
+            --pvalue <N> "Align N shuffles of s2 the same way and report the z-score and p-values of the real alignment"
+        ))
+        .arg(arg!(
+            --shuffle <KIND> "What shuffles keep: mono (character counts) or di (neighbouring pair counts)"
+        ).default_value("mono"))
+        .arg(arg!(
+            --"both-strands" "Also align the reverse complement of s2 (DNA) and report whichever strand scores better"
+        ))
+        .arg(arg!(
+            --codon "Globally align coding sequences a codon at a time, scoring codons by their amino acids, for ALG 0"
+        ))
+        .arg(arg!(
+            --frameshift <P> "Penalty for each gap of 1 or 2 characters that breaks the reading frame of a codon or translated alignment"
+        ).default_value("-20"))
+        .arg(arg!(
+            --translated "Locally align DNA s1, translated in all six frames, against protein s2, for ALG 1"
+        ))
+        .arg(arg!(
+            --"ignore-case" "Score lowercase (soft-masked) characters like uppercase ones"
+        ))
+        .arg(arg!(
+            --"mask-seeds" "Keep local alignments from starting on lowercase (soft-masked) characters, though they can run through them"
+        ))
+        .arg(arg!(
+            --mask <MASKER> "Soft-mask low-complexity regions of every string before aligning, with dust (DNA) or seg (protein), ignoring case"
+        ))
+        .arg(arg!(
+            --"gap-track" <FILE> "Multiply the gap open penalties at each character of s1 by the numbers in FILE, one per character"
+        ))
+        .arg(arg!(
+            --"count-optimal" "Count how many different alignments reach the optimal score, for unbanded ALG 0 or 1"
+        ))
+        .arg(arg!(
+            --enumerate <N> "Also show up to N of the co-optimal alignments"
+        ))
+        .arg(arg!(
+            --sample "Show one co-optimal alignment picked uniformly at random instead of the one the retrace prefers"
+        ))
+        .arg(arg!(
+            --seed <SEED> "Seed for shuffling, so runs can be repeated"
+        ))
         .get_matches(); // run clap
 
-    // Initialize default config settings
-    let mut config = Config {
-        true_match: 1,
-        mismatch: -2,
-        h: -5,
-        g: -1,
-        s1_name: String::new(),
-        s2_name: String::new(),
+    // a tree from existing distances needs no alignment at all
+    if let Some(file) = args.get_one::<String>("distances") {
+        let distances = from_phylip(&read_file(file)).unwrap_or_else(|err| panic!("Error reading the distance matrix: {}", err));
+        let method = args.get_one::<String>("tree").map(String::as_str).unwrap_or("nj");
+        println!("{}", build_tree(method, &distances).to_newick());
+        return;
+    }
+
+    // Read the config file and pick the profile to run with
+    let parameters_file: &str = match args.get_one::<String>("CONFIG") { //grab either the provided config or the default
+        None => "configs/parameters.config",
+        Some(file) => file,
+    };
+    let profile = args.get_one::<String>("profile").map_or(DEFAULT_PROFILE, String::as_str);
+    let mut config = fs::read_to_string(parameters_file)
+        .map_err(|err| ConfigError { line: 0, message: err.to_string() })
+        .and_then(|text| ConfigFile::parse(&text))
+        .and_then(|config_file| config_file.config(profile))
+        .unwrap_or_else(|err| {
+            eprintln!("Error in config {}: {}", parameters_file, err);
+            process::exit(1);
+        });
+    
+    // Read the strings file
+    let strings_file: &str = match args.get_one::<String>("FILE") { //grab either the provided config or the default
+        None => panic!("Missing required command-line option: FILE"),
+        Some(file) => file,
     };
+    let mut string_vec = read_fasta(&read_file(strings_file)).unwrap_or_else(|err| panic!("Input strings were in the wrong format: {}", err));
+
+    if string_vec.len() < 2 {
+        panic!("Input strings file needs at least two strings");
+    }
+    config.s1_name = string_vec[0].name.clone();
+    config.s2_name = string_vec[1].name.clone();
+    config.ignore_case |= args.get_flag("ignore-case");
+    config.mask_seeds |= args.get_flag("mask-seeds");
+    let gap_track_file = args.get_one::<String>("gap-track");
+    if let Some(file) = gap_track_file {
+        let track = parse_gap_track(&read_file(file)).unwrap_or_else(|err| panic!("Error parsing the gap track: {}", err));
+        if track.len() != string_vec[0].str.len() {
+            panic!("The gap track has {} multipliers but sequence 1 has {} characters", track.len(), string_vec[0].str.len());
+        }
+        config.gap_track = Some(track);
+    }
+
+    // lowercase the low-complexity regions, which then only matter where masks are looked at
+    let masker: Option<Masker> = args.get_one::<String>("mask")
+        .map(|name| Masker::parse(name).unwrap_or_else(|err| panic!("Error parsing the masker: {}", err)));
+    let mut masked: Vec<usize> = Vec::new();
+    if let Some(masker) = masker {
+        for string in string_vec.iter_mut() {
+            let mask = masker.mask(&string.str);
+            masked.push(mask.iter().filter(|&&masked| masked).count());
+            string.str = soft_mask(&string.str, &mask);
+        }
+        config.ignore_case = true;
+    }
 
-    // Read the config file
-    let parameters_file: &str;
-    match args.get_one::<String>("CONFIG") { //grab either the provided config or the default
-        None => {
-            parameters_file = "configs/parameters.config";
+    let alg = match args.get_one::<String>("ALG").unwrap().parse::<i32>() {
+        Err(_) => {
+            panic!("Missing required command-line option: ALG");
         },
-        Some(file) => {
-            parameters_file = &file;
+        Ok(alg @ 0..=4) => alg,
+        _ => {
+            panic!("Invalid input for required command-line option: ALG");
         }
+    };
+    let memory: usize = args.get_one::<String>("memory").unwrap().parse().expect("Error parsing the memory budget.");
+    let band: Option<usize> = args.get_one::<String>("band").map(|band| band.parse().expect("Error parsing the band width."));
+    if band.is_some() && alg > 1 {
+        panic!("A band can only be used with global or local alignment");
     }
-    let parameters = read_file(parameters_file);
-    let config_lines = parameters.lines();
-    for line in config_lines {
-        let words: Vec<&str> = line.split_ascii_whitespace().collect();
-        match words[0] {
-            "match" => {
-                config.true_match = words[1].parse().expect("Error parsing a match score.");
-            },
-            "mismatch" => {
-                config.mismatch = words[1].parse().expect("Error parsing a mismatch score.");
+    let (kind, ends) = match alg { //end-gap-free alignments use the preset ends unless they were given
+        0 => (AlignmentKind::Global, EndGaps::parse("").unwrap()),
+        1 => (AlignmentKind::Local, EndGaps::parse("").unwrap()),
+        2 => (AlignmentKind::SemiGlobal, EndGaps::semi_global()),
+        3 => (AlignmentKind::Overlap, EndGaps::overlap()),
+        _ => (AlignmentKind::Glocal, EndGaps::glocal()),
+    };
+    let codon = args.get_flag("codon");
+    let translated = args.get_flag("translated");
+    let frameshift: i32 = args.get_one::<String>("frameshift").unwrap().parse().expect("Error parsing the frameshift penalty.");
+    let ends = match args.get_one::<String>("free-ends") {
+        None => ends,
+        Some(list) => EndGaps::parse(list).unwrap_or_else(|err| panic!("Error parsing the free ends: {}", err)),
+    };
+
+    // threads go to the pairs when there are many of them, otherwise to the one matrix
+    let threads: usize = args.get_one::<String>("threads").unwrap().parse().expect("Error parsing the thread count.");
+    let many_pairs = args.contains_id("all-pairs") || args.contains_id("msa");
+    let fill_threads = if many_pairs {1} else {threads};
+    let both_strands = args.get_flag("both-strands");
+    if both_strands && many_pairs {
+        panic!("Both strands can only be searched when aligning one pair of strings");
+    }
+    if codon && (alg != 0 || band.is_some() || many_pairs || both_strands || args.get_flag("score-only")) {
+        panic!("Codon alignment is one full global alignment, so it needs ALG 0 without --band, --score-only, --both-strands, --all-pairs or --msa");
+    }
+    if gap_track_file.is_some() && (codon || translated || many_pairs || args.get_flag("linear")) {
+        panic!("A gap track follows sequence 1 through one full alignment, so it can't be used with --codon, --translated, --linear, --all-pairs or --msa");
+    }
+    let other_gaps = config.gap_model != GapModel::Affine;
+    let shuffles: usize = args.get_one::<String>("shuffles").unwrap().parse().expect("Error parsing the number of shuffles.");
+    let ungapped = args.get_flag("ungapped");
+    if !ungapped && shuffles < 2 {
+        panic!("Fitting lambda and K takes at least 2 shuffles, or --ungapped for the ungapped values");
+    }
+    if other_gaps && (alg > 1 || band.is_some() || codon || translated || args.contains_id("msa") || gap_track_file.is_some() || config.mask_seeds
+        || ["score-only", "linear"].iter().any(|&id| args.get_flag(id)) || args.contains_id("top")) {
+        panic!("The {} gap model only runs full global or local alignment, so it needs ALG 0 or 1 without --band, --score-only, --top, --linear, --gap-track, --mask-seeds, --codon, --translated or --msa", config.gap_model.name());
+    }
+    let enumerate: Option<usize> = args.get_one::<String>("enumerate").map(|n| n.parse().expect("Error parsing the number of alignments to enumerate."));
+    let co_optimal = args.get_flag("count-optimal") || args.get_flag("sample") || enumerate.is_some();
+    if co_optimal && (alg > 1 || band.is_some() || other_gaps || codon || translated || many_pairs || both_strands
+        || ["score-only", "linear"].iter().any(|&id| args.get_flag(id)) || args.contains_id("top")) {
+        panic!("Co-optimal alignments are counted over the full matrix of one affine global or local alignment, so they need ALG 0 or 1 without --band, --score-only, --top, --linear, --both-strands, --codon, --translated, --all-pairs, --msa or another gap model");
+    }
+    let pairwise_only = band.is_some() || args.get_flag("score-only") || ["top", "sam", "paf", "pvalue"].iter().any(|&id| args.contains_id(id));
+    if translated && (alg != 1 || many_pairs || both_strands || codon || pairwise_only) {
+        panic!("Translated alignment is one local alignment over both strands, so it needs ALG 1 without --band, --score-only, --top, --sam, --paf, --pvalue, --both-strands, --codon, --all-pairs or --msa");
+    }
+
+    // ambiguity codes are neither matches nor mismatches when they're scored as such
+    let iupac = config.matrix.as_ref().is_some_and(|matrix| matrix.name == IUPAC);
+    let set_aside = |mut alignment: Alignment| {
+        if iupac {
+            alignment.set_aside_ambiguous();
+        }
+        alignment
+    };
+
+    // runs the chosen algorithm on one pair of strings
+    let align = |s1: &str, s2: &str| -> (Alignment, Option<BandReport>) {
+        if let Some(band) = band { //a band replaces the full matrix for either algorithm
+            let (alignment, band_report) = banded(s1, s2, &config, alg == 1, band, args.get_flag("widen"));
+            return (set_aside(alignment), Some(band_report));
+        }
+        if other_gaps {
+            return (set_aside(gap_model_align(s1, s2, &config, alg == 1)), None);
+        }
+        let alignment = match alg {
+            0 => { // run needleman-wunsch, or myers-miller if the matrix wouldn't fit in memory and there's no gap track
+                let matrix_bytes = (s1.len()+1) * (s2.len()+1) * std::mem::size_of::<Cell>();
+                if config.gap_track.is_none() && (args.get_flag("linear") || matrix_bytes > memory * 1024 * 1024) {
+                    myers_miller(s1, s2, &config)
+                } else if fill_threads > 1 {
+                    wavefront_needleman_wunsch(s1, s2, &config, fill_threads)
+                } else {
+                    needleman_wunsch(s1, s2, &config)
+                }
             },
-            "h" => {
-                config.h = words[1].parse().expect("Error parsing an h score.");
+            1 => { // run smith-waterman
+                if fill_threads > 1 {
+                    wavefront_smith_waterman(s1, s2, &config, fill_threads)
+                } else {
+                    smith_waterman(s1, s2, &config)
+                }
             },
-            "g" => {
-                config.g = words[1].parse().expect("Error parsing a g score.");
+            _ => { // run an end-gap-free alignment
+                end_gap_free(s1, s2, &config, &ends, kind)
             },
-            _ => {
-                println!("found something else idk man");
-            }
+        };
+        (set_aside(alignment), None)
+    };
+
+    // align every pair of strings and write out the matrices
+    if let Some(prefix) = args.get_one::<String>("all-pairs") {
+        let names: Vec<String> = string_vec.iter().map(|string| string.name.clone()).collect();
+        let strings: Vec<&str> = string_vec.iter().map(|string| string.str.as_str()).collect();
+        let matrices = all_vs_all(&names, &strings, &config, threads, |s1, s2| align(s1, s2).0);
+
+        let mut outputs = vec![
+            (format!("{}.scores.tsv", prefix), to_tsv(&names, &matrices.scores)),
+            (format!("{}.identity.tsv", prefix), to_tsv(&names, &matrices.identities)),
+            (format!("{}.distance.tsv", prefix), to_tsv(&names, &matrices.distances.distances)),
+            (format!("{}.phylip", prefix), to_phylip(&matrices.distances)),
+        ];
+        if let Some(method) = args.get_one::<String>("tree") {
+            outputs.push((format!("{}.{}.newick", prefix, method), build_tree(method, &matrices.distances).to_newick() + "\n"));
         }
-    }
-    
-    // Read the strings file
-    let strings_file: &str;
-    match args.get_one::<String>("FILE") { //grab either the provided config or the default
-        None => {
-            panic!("Missing required command-line option: FILE");
-        },
-        Some(file) => {
-            strings_file = &file;
+        for (file, contents) in outputs {
+            fs::write(&file, contents).expect("Unable to write file");
+            println!("Wrote {}", file);
         }
+        return;
     }
-    let strings = read_file(strings_file);
-    let string_lines = strings.lines();
-    let mut string_vec: Vec<NamedString> = Vec::new();
-    for line in string_lines {
-        if line.starts_with(">") { // skip this line but push a new string
-            string_vec.push(NamedString::name(&line[1..line.len()])); //copy from the 1st index to the end
-            continue;
-        } else {
-            string_vec.last_mut().expect("Input strings were in the wrong format").str.push_str(line);
+
+    // align all the strings together, guided by a tree of their pairwise scores
+    if let Some(prefix) = args.get_one::<String>("msa") {
+        let names: Vec<String> = string_vec.iter().map(|string| string.name.clone()).collect();
+        let strings: Vec<&str> = string_vec.iter().map(|string| string.str.as_str()).collect();
+        let matrices = all_vs_all(&names, &strings, &config, threads, |s1, s2| align(s1, s2).0);
+        let guide = upgma(&guide_distances(&names, &matrices.scores));
+        let profile = progressive(&strings, &guide, &config, memory * 1024 * 1024).unwrap_or_else(|err| panic!("{}", err));
+
+        let outputs = [
+            (format!("{}.aln", prefix), to_clustal(&names, &profile)),
+            (format!("{}.fasta", prefix), to_aligned_fasta(&names, &profile)),
+        ];
+        for (file, contents) in outputs {
+            fs::write(&file, contents).expect("Unable to write file");
+            println!("Wrote {}", file);
         }
+        return;
     }
 
-    config.s1_name = string_vec[0].name.clone();
-    config.s2_name = string_vec[1].name.clone();
     println!("OUTPUT:");
     println!("********\n");
-    println!("Scores:    match = {}, mismatch = {}, h = {}, g = {}", config.true_match, config.mismatch, config.h, config.g);
+    let gaps = if config.h1 == config.h2 && config.g1 == config.g2 {
+        format!("h = {}, g = {}", config.h1, config.g1)
+    } else {
+        format!("h1 = {}, g1 = {} (gaps in sequence 1), h2 = {}, g2 = {} (gaps in sequence 2)", config.h1, config.g1, config.h2, config.g2)
+    };
+    match &config.matrix {
+        Some(matrix) if iupac => println!("Scores:    matrix = {} (match = {}, mismatch = {}, N = 0), {}",
+            matrix.name, config.true_match, config.mismatch, gaps),
+        Some(matrix) => println!("Scores:    matrix = {}, {}", matrix.name, gaps),
+        None => println!("Scores:    match = {}, mismatch = {}, {}", config.true_match, config.mismatch, gaps),
+    }
+    match config.gap_model {
+        GapModel::Affine => {},
+        GapModel::TwoPiece { h1, g1, h2, g2 } if (h1, g1) == (h2, g2) => println!("Gap model: two-piece, long gaps h = {}, g = {}, whichever line scores better", h1, g1),
+        GapModel::TwoPiece { h1, g1, h2, g2 } => println!("Gap model: two-piece, long gaps h1 = {}, g1 = {} (gaps in sequence 1), h2 = {}, g2 = {} (gaps in sequence 2), whichever line scores better",
+            h1, g1, h2, g2),
+        model => println!("Gap model: {}, a gap of length k scores h + g*(1 + ln k)", model.name()),
+    }
+    if let (Some(file), Some(track)) = (gap_track_file, &config.gap_track) {
+        let lowest = track.iter().cloned().fold(f64::INFINITY, f64::min);
+        let highest = track.iter().cloned().fold(0.0, f64::max);
+        println!("Gap track: {}, open multipliers {} to {} along sequence 1", file, lowest, highest);
+    }
     println!();
     println!("Sequence 1 = \"{}\", length = {} characters", string_vec[0].name, string_vec[0].str.len());
     println!("Sequence 2 = \"{}\", length = {} characters", string_vec[1].name, string_vec[1].str.len());
     println!();
+    if let Some(masker) = masker {
+        println!("Masked ({}):  sequence 1 = {} characters, sequence 2 = {} characters\n", masker.name(), masked[0], masked[1]);
+    }
+    if config.ignore_case || config.mask_seeds {
+        println!("Lowercase:  ignore case = {}, no local seeds = {}\n", config.ignore_case, config.mask_seeds);
+    }
+    let s2_len = string_vec[1].str.len();
+    let s2_reversed: Option<String> = if both_strands {
+        println!("Searching both strands of sequence 2\n");
+        Some(reverse_complement(&string_vec[1].str))
+    } else {
+        None
+    };
 
-    match args.get_one::<String>("ALG").unwrap().parse::<i32>() {
-        Err(_) => {
-            panic!("Missing required command-line option: ALG");
-        },
-        Ok(0) => { // run needleman-wunsch
-            needleman_wunsch(&string_vec[0].str, &string_vec[1].str, &config);
-        },
-        Ok(1) => { // run smith-waterman
-            smith_waterman(&string_vec[0].str, &string_vec[1].str, &config);
-        },
-        _ => {
-            panic!("Invalid input for required command-line option: ALG");
+    // DNA against protein has its own rows and report
+    if translated {
+        match translated_align(&string_vec[0].str, &string_vec[1].str, &config, frameshift) {
+            Some(alignment) => print!("{}", render_translated(&alignment, &config)),
+            None => println!("No translated alignment scores above 0"),
         }
+        return;
     }
-}
 
+    // skip the retrace entirely when only the score is wanted
+    if args.get_flag("score-only") {
+        if alg > 1 {
+            panic!("Only global or local alignment can compute the score alone");
+        }
+        if alg == 1 { //local scores go through the vectorized fill when the CPU has one
+            println!("Vectorized with {}\n", Backend::detect().name());
+        }
+        let score = |s2: &str| if alg == 1 {
+            striped_smith_waterman(&string_vec[0].str, s2, &config)
+        } else {
+            optimal_score(&string_vec[0].str, s2, &config, false)
+        };
+        let forward = score(&string_vec[1].str);
+        match s2_reversed.as_deref().map(score) {
+            Some(reverse) if reverse > forward => println!("{} optimal score = {}, Strand = Plus/Minus", kind.name(), reverse),
+            Some(_) => println!("{} optimal score = {}, Strand = Plus/Plus", kind.name(), forward),
+            None => println!("{} optimal score = {}", kind.name(), forward),
+        }
+        return;
+    }
+
+    // local alignments are judged against the scores of random strings
+    let seed: u64 = args.get_one::<String>("seed").map_or(DEFAULT_SEED, |seed| seed.parse().expect("Error parsing the seed."));
+    let mut rng = Rng::new(seed);
+    let s1 = &string_vec[0].str;
+    let shuffled_score = |shuffled: &str| if alg == 1 && band.is_none() && !other_gaps { //the vectorized fill gives the same local score much faster
+        striped_smith_waterman(s1, shuffled, &config)
+    } else {
+        align(s1, shuffled).0.score
+    };
+    let stats: Option<KarlinAltschul> = if alg == 1 {
+        let stats = if ungapped {
+            KarlinAltschul::ungapped(s1, &string_vec[1].str, &config)
+        } else {
+            KarlinAltschul::from_shuffles(s1.len(), &string_vec[1].str, shuffles, &mut rng, shuffled_score)
+        };
+        match &stats {
+            Some(stats) => println!("{}", render_parameters(stats)),
+            None => println!("Statistics:  none, the scores don't behave like a local alignment scheme on these strings\n"),
+        }
+        stats
+    } else {
+        None
+    };
+    let shuffle_test: Option<ShuffleTest> = args.get_one::<String>("pvalue").map(|shuffles| {
+        let shuffles: usize = shuffles.parse().expect("Error parsing the number of shuffles.");
+        let kind = Shuffle::parse(args.get_one::<String>("shuffle").unwrap()).unwrap_or_else(|err| panic!("Error parsing the shuffle: {}", err));
+        ShuffleTest::run(&string_vec[1].str, shuffles, kind, &mut rng, shuffled_score)
+            .unwrap_or_else(|| panic!("Every shuffle scored the same, so there is no distribution to compare against"))
+    });
+    let significance = |alignment: &Alignment| {
+        if let Some(stats) = &stats {
+            print!("{}", render_significance(alignment, stats, string_vec[0].str.len(), string_vec[1].str.len()));
+        }
+        if let Some(test) = &shuffle_test {
+            print!("{}", render_shuffle_test(alignment, test));
+        }
+    };
+
+    // report several local alignments, best first, or just the one best alignment
+    let alignments: Vec<Alignment> = if let Some(top) = args.get_one::<String>("top") {
+        if alg != 1 || band.is_some() {
+            panic!("Only unbanded local alignment can report the top alignments");
+        }
+        let top: usize = top.parse().expect("Error parsing the number of alignments.");
+        let min_score: i32 = args.get_one::<String>("min-score").unwrap().parse().expect("Error parsing the minimum score.");
+        let mut alignments = waterman_eggert(&string_vec[0].str, &string_vec[1].str, &config, top, min_score);
+        if let Some(s2_reversed) = &s2_reversed { //the best of either strand, forward first on ties
+            alignments.extend(waterman_eggert(&string_vec[0].str, s2_reversed, &config, top, min_score)
+                .into_iter()
+                .map(|alignment| alignment.on_reverse_strand(s2_len)));
+            alignments.sort_by_key(|alignment| -alignment.score);
+            alignments.truncate(top);
+        }
+        let alignments: Vec<Alignment> = alignments.into_iter().map(set_aside).collect();
+        for (k, alignment) in alignments.iter().enumerate() {
+            println!("Alignment {} of {}: s1 {}-{}, s2 {}-{} ({}), score = {}\n", k+1, alignments.len(),
+                alignment.s1_start+1, alignment.s1_end, alignment.s2_start+1, alignment.s2_end, alignment.strand.name(), alignment.score);
+            print!("{}", render_alignment(alignment, &config));
+            significance(alignment);
+            println!("\n");
+        }
+        if alignments.is_empty() {
+            println!("No local alignment scores at least {}", min_score);
+        }
+        alignments
+    } else if codon {
+        let (alignment, codon_report) = codon_align(&string_vec[0].str, &string_vec[1].str, &config, frameshift);
+        print!("{}", render_alignment(&alignment, &config));
+        println!();
+        print!("{}", render_codon_report(&codon_report));
+        vec![alignment]
+    } else if co_optimal {
+        let (s1, s2) = (&string_vec[0].str, &string_vec[1].str);
+        let bytes = CoOptimal::bytes_needed(s1.len(), s2.len());
+        if bytes > memory * 1024 * 1024 {
+            panic!("Counting co-optimal alignments of these strings needs at least {} MB, more than --memory allows", bytes.div_ceil(1024 * 1024));
+        }
+        let co_optimal = CoOptimal::new(s1, s2, &config, alg == 1, fill_threads);
+        let alignment = if args.get_flag("sample") {
+            co_optimal.sample(&mut rng).map_or_else(|| align(s1, s2).0, set_aside)
+        } else {
+            align(s1, s2).0
+        };
+        print!("{}", render_alignment(&alignment, &config));
+        significance(&alignment);
+        println!("\nCo-optimal alignments = {}", co_optimal.count());
+        if let Some(limit) = enumerate {
+            let others: Vec<Alignment> = co_optimal.enumerate(limit).into_iter().map(set_aside).collect();
+            for (k, other) in others.iter().enumerate() {
+                println!("\n\nCo-optimal alignment {} of {}: s1 {}-{}, s2 {}-{}\n", k+1, others.len(),
+                    other.s1_start+1, other.s1_end, other.s2_start+1, other.s2_end);
+                print!("{}", render_alignment(other, &config));
+            }
+        }
+        vec![alignment]
+    } else {
+        let (mut alignment, mut band_report) = align(&string_vec[0].str, &string_vec[1].str);
+        if let Some(s2_reversed) = &s2_reversed { //keep the forward strand unless the reverse one does better
+            let (reverse, reverse_report) = align(&string_vec[0].str, s2_reversed);
+            if reverse.score > alignment.score {
+                alignment = reverse.on_reverse_strand(s2_len);
+                band_report = reverse_report;
+            }
+        }
+        print!("{}", render_alignment(&alignment, &config));
+        significance(&alignment);
+        if let Some(band_report) = band_report {
+            println!("\nBand = {}, touched edge = {}, provably optimal = {}",
+                band_report.band, band_report.touched_edge, band_report.optimal);
+        }
+        vec![alignment]
+    };
+
+    // write the alignments out for other tools
+    let reference = Record { name: &string_vec[0].name, seq: &string_vec[0].str };
+    let query = Record { name: &string_vec[1].name, seq: &string_vec[1].str };
+    let outputs = [
+        (args.get_one::<String>("sam"), to_sam as fn(&[Alignment], Record, Record) -> String),
+        (args.get_one::<String>("paf"), to_paf),
+    ];
+    for (file, writer) in outputs {
+        if let Some(file) = file {
+            fs::write(file, writer(&alignments, reference, query)).expect("Unable to write file");
+            println!("Wrote {}", file);
+        }
+    }
+}
 
 
 // ATGGCGT
@@ -0,0 +1,183 @@
+/// Width of the DUST window, in nucleotides
+const DUST_WINDOW: usize = 64;
+
+/// DUST score above which a window is low complexity, on NCBI's scale of ten times the mean repeat count
+const DUST_THRESHOLD: f64 = 20.0;
+
+/// Width of the SEG window, in residues
+const SEG_WINDOW: usize = 12;
+
+/// Entropy, in bits, at or below which a SEG window starts a low-complexity region
+const SEG_TRIGGER: f64 = 2.2;
+
+/// Entropy at or below which neighbouring windows extend a region
+const SEG_EXTEND: f64 = 2.5;
+
+
+/// Which low-complexity masker to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Masker {
+    Dust, //for DNA
+    Seg, //for protein
+}
+impl Masker {
+
+    /// Parses "dust" or "seg"
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "dust" => Ok(Masker::Dust),
+            "seg" => Ok(Masker::Seg),
+            _ => Err(format!("unknown masker \"{}\", expected dust or seg", name)),
+        }
+    }
+
+    /// Name to show in the output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Masker::Dust => "DUST",
+            Masker::Seg => "SEG",
+        }
+    }
+
+    /// Which characters of the string are in low-complexity regions
+    pub fn mask(&self, s: &str) -> Vec<bool> {
+        match self {
+            Masker::Dust => dust(s),
+            Masker::Seg => seg(s),
+        }
+    }
+}
+
+
+/// Lowercases the masked characters, the way soft-masked FASTA marks them
+pub fn soft_mask(s: &str, mask: &[bool]) -> String {
+    s.chars().zip(mask).map(|(c, &masked)| if masked {c.to_ascii_lowercase()} else {c}).collect()
+}
+
+
+/// DUST: a window is low complexity when its triplets repeat too much, scoring 10 * sum c(c-1)/2 / (l-1) over the counts c of
+/// each of the 64 triplets among its l triplets. Every window over the threshold is masked whole.
+pub fn dust(s: &str) -> Vec<bool> {
+    let bytes = s.as_bytes();
+    let mut mask = vec![false; bytes.len()];
+    if bytes.len() < 3 {
+        return mask;
+    }
+    let code = |c: u8| match c.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' | b'U' => Some(3),
+        _ => None,
+    };
+    let triplet = |k: usize| Some(code(bytes[k])? * 16 + code(bytes[k+1])? * 4 + code(bytes[k+2])?); //starting at k, if it's all bases
+
+    // slide the window along, keeping the triplet counts and the sum of pairs of equal triplets up to date
+    let window = DUST_WINDOW.min(bytes.len());
+    let triplets = window - 2;
+    let mut counts = [0usize; 64];
+    let mut pairs = 0;
+    for k in 0..bytes.len()-2 {
+        if let Some(t) = triplet(k) { //a triplet enters on the right
+            pairs += counts[t];
+            counts[t] += 1;
+        }
+        if k >= triplets {
+            if let Some(t) = triplet(k - triplets) { //and one leaves on the left
+                counts[t] -= 1;
+                pairs -= counts[t];
+            }
+        }
+        if k + 1 >= triplets && triplets > 1 && 10.0 * pairs as f64 / (triplets - 1) as f64 > DUST_THRESHOLD {
+            let start = k + 1 - triplets;
+            mask[start..start+window].iter_mut().for_each(|masked| *masked = true);
+        }
+    }
+    mask
+}
+
+
+/// SEG: windows whose residues have low Shannon entropy start a low-complexity region, which grows over neighbouring
+/// windows that are nearly as simple. Every window in a region is masked whole.
+pub fn seg(s: &str) -> Vec<bool> {
+    let bytes = s.as_bytes();
+    let mut mask = vec![false; bytes.len()];
+    if bytes.len() < SEG_WINDOW {
+        return mask;
+    }
+
+    // entropy of the window starting at each position
+    let mut counts = [0usize; 256];
+    bytes[..SEG_WINDOW].iter().for_each(|&c| counts[c.to_ascii_uppercase() as usize] += 1);
+    let entropy = |counts: &[usize; 256]| -> f64 {
+        counts.iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / SEG_WINDOW as f64;
+                -p * p.log2()
+            })
+            .sum()
+    };
+    let mut entropies = vec![entropy(&counts)];
+    for start in 1..bytes.len()-SEG_WINDOW+1 {
+        counts[bytes[start-1].to_ascii_uppercase() as usize] -= 1;
+        counts[bytes[start+SEG_WINDOW-1].to_ascii_uppercase() as usize] += 1;
+        entropies.push(entropy(&counts));
+    }
+
+    // grow a region out of every trigger window
+    let mut start = 0;
+    while start < entropies.len() {
+        if entropies[start] > SEG_TRIGGER {
+            start += 1;
+            continue;
+        }
+        let mut first = start;
+        while first > 0 && entropies[first-1] <= SEG_EXTEND {
+            first -= 1;
+        }
+        let mut last = start;
+        while last + 1 < entropies.len() && entropies[last+1] <= SEG_EXTEND {
+            last += 1;
+        }
+        mask[first..last+SEG_WINDOW].iter_mut().for_each(|masked| *masked = true);
+        start = last + 1;
+    }
+    mask
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::testing::random_string;
+
+    #[test]
+    fn dust_masks_repeats_but_not_random_dna() {
+        let mut rng = Rng::new(21);
+        let flank = random_string(&mut rng, b"ACGT", 100);
+        for repeat in ["A".repeat(70), "AC".repeat(35), "CAG".repeat(24)] {
+            let s = format!("{}{}{}", flank, repeat, flank);
+            let mask = dust(&s);
+            assert!(mask[100..100+repeat.len()].iter().all(|&masked| masked), "{}", repeat);
+            assert!(mask.iter().filter(|&&masked| masked).count() < repeat.len() + 2 * DUST_WINDOW, "{}", repeat);
+        }
+        for _ in 0..20 {
+            let s = random_string(&mut rng, b"ACGT", 500);
+            assert!(dust(&s).iter().all(|&masked| !masked), "{}", s);
+        }
+        assert_eq!(dust("AA"), vec![false, false]);
+    }
+
+    #[test]
+    fn seg_masks_a_low_entropy_stretch() {
+        let mut rng = Rng::new(22);
+        let flank = random_string(&mut rng, b"ACDEFGHIKLMNPQRSTVWY", 60);
+        let s = format!("{}{}{}", flank, "PQPQQPPQPPQQPQPQ", flank);
+        let mask = seg(&s);
+        assert!(mask[60..76].iter().all(|&masked| masked));
+        assert!(!mask[..40].iter().any(|&masked| masked) && !mask[96..].iter().any(|&masked| masked));
+        assert_eq!(soft_mask("MKPPPV", &[false, false, true, true, true, false]), "MKpppV");
+    }
+}
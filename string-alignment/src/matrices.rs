@@ -0,0 +1,240 @@
+use std::fs;
+use crate::dna::bases;
+
+
+/// Matrices that ship with the program, in NCBI format
+const BUILTIN: [(&str, &str); 6] = [
+    ("BLOSUM45", include_str!("../matrices/BLOSUM45")),
+    ("BLOSUM62", include_str!("../matrices/BLOSUM62")),
+    ("BLOSUM80", include_str!("../matrices/BLOSUM80")),
+    ("PAM30", include_str!("../matrices/PAM30")),
+    ("PAM70", include_str!("../matrices/PAM70")),
+    ("PAM250", include_str!("../matrices/PAM250")),
+];
+
+
+/// Name of the nucleotide matrix built from the match and mismatch scores, rather than read from a file
+pub const IUPAC: &str = "IUPAC";
+
+
+/// Scores for aligning any pair of characters
+#[derive(Debug, Clone)]
+pub struct SubstitutionMatrix {
+    pub name: String,
+    scores: Vec<i32>, //256x256 lookup table indexed by raw bytes
+    max_score: i32,
+}
+impl SubstitutionMatrix {
+
+    /// Parses a matrix in the NCBI format: '#' comments, a header row of characters, then one labelled row per character
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut lines = text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+        // the header gives the column order
+        let columns: Vec<u8> = match lines.next() {
+            None => return Err(format!("matrix {} is empty", name)),
+            Some((num, line)) => {
+                let mut columns = Vec::new();
+                for word in line.split_ascii_whitespace() {
+                    if word.len() != 1 {
+                        return Err(format!("matrix {} line {}: expected single characters in the header, found \"{}\"", name, num+1, word));
+                    }
+                    columns.push(word.as_bytes()[0]);
+                }
+                columns
+            }
+        };
+
+        // read every row, remembering which pairs were given
+        let mut given: Vec<(u8, u8, i32)> = Vec::with_capacity(columns.len() * columns.len());
+        for (num, line) in lines {
+            let words: Vec<&str> = line.split_ascii_whitespace().collect();
+            if words[0].len() != 1 {
+                return Err(format!("matrix {} line {}: expected a single character row label, found \"{}\"", name, num+1, words[0]));
+            }
+            if words.len() != columns.len() + 1 {
+                return Err(format!("matrix {} line {}: expected {} scores, found {}", name, num+1, columns.len(), words.len() - 1));
+            }
+            let row = words[0].as_bytes()[0];
+            for (col, word) in columns.iter().zip(&words[1..]) {
+                let score = word.parse::<i32>()
+                    .map_err(|_| format!("matrix {} line {}: \"{}\" is not a score", name, num+1, word))?;
+                given.push((row, *col, score));
+            }
+        }
+        if given.is_empty() {
+            return Err(format!("matrix {} has no rows", name));
+        }
+
+        let min_score = given.iter().map(|&(_, _, score)| score).min().unwrap();
+        let max_score = given.iter().map(|&(_, _, score)| score).max().unwrap();
+
+        // characters outside the matrix score like '*' if it exists, or the worst score otherwise
+        let mut scores = vec![min_score; 256*256];
+        if columns.contains(&b'*') {
+            let star: Vec<(u8, u8, i32)> = given.iter().filter(|(r, c, _)| *r == b'*' || *c == b'*').cloned().collect();
+            for (row, col, score) in star {
+                if row == b'*' && col == b'*' {
+                    continue;
+                }
+                let other = if row == b'*' {col} else {row};
+                for c in 0..256 {
+                    scores[other as usize * 256 + c] = score;
+                    scores[c * 256 + other as usize] = score;
+                }
+            }
+        }
+
+        // lowercase characters score the same as their uppercase counterparts, unless the matrix says otherwise
+        for &(row, col, score) in &given {
+            for r in [row.to_ascii_lowercase(), row] {
+                for c in [col.to_ascii_lowercase(), col] {
+                    scores[r as usize * 256 + c as usize] = score;
+                }
+            }
+        }
+
+        Ok(SubstitutionMatrix {
+            name: String::from(name),
+            scores,
+            max_score,
+        })
+    }
+
+    /// Returns one of the built-in BLOSUM or PAM matrices
+    pub fn builtin(name: &str) -> Option<Self> {
+        BUILTIN.iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+            .map(|(builtin, text)| SubstitutionMatrix::parse(builtin, text).expect("Built-in matrix is malformed"))
+    }
+
+    /// Scores nucleotides with the IUPAC ambiguity codes by the expected score over every pair of bases they stand for,
+    /// rounded to the nearest whole number. N scores 0 against anything, and characters that aren't nucleotides score as usual.
+    pub fn iupac(true_match: i32, mismatch: i32) -> Self {
+        let mut scores = vec![0; 256*256];
+        for c1 in 0..256 {
+            for c2 in 0..256 {
+                let (b1, b2) = (bases(c1 as u8), bases(c2 as u8));
+                scores[c1 * 256 + c2] = if b1 == 0 || b2 == 0 {
+                    if c1 == c2 {true_match} else {mismatch}
+                } else if b1 == 0b1111 || b2 == 0b1111 { //N could be anything, so it neither helps nor hurts
+                    0
+                } else {
+                    let pairs = b1.count_ones() * b2.count_ones();
+                    let shared = (b1 & b2).count_ones();
+                    let expected = (shared as i32 * true_match + (pairs - shared) as i32 * mismatch) as f64 / pairs as f64;
+                    expected.round() as i32
+                };
+            }
+        }
+        SubstitutionMatrix {
+            name: String::from(IUPAC),
+            scores,
+            max_score: true_match.max(mismatch).max(0),
+        }
+    }
+
+    /// Returns the built-in matrix with this name, or reads the matrix from the file at this path
+    pub fn load(name_or_path: &str) -> Result<Self, String> {
+        if let Some(matrix) = SubstitutionMatrix::builtin(name_or_path) {
+            return Ok(matrix);
+        }
+        let text = fs::read_to_string(name_or_path)
+            .map_err(|err| format!("{} is not a built-in matrix and could not be read: {}", name_or_path, err))?;
+        SubstitutionMatrix::parse(name_or_path, &text)
+    }
+
+    /// Score of aligning c1 with c2
+    #[inline]
+    pub fn score(&self, c1: u8, c2: u8) -> i32 {
+        self.scores[c1 as usize * 256 + c2 as usize]
+    }
+
+    /// Highest score anywhere in the matrix
+    pub fn max_score(&self) -> i32 {
+        self.max_score
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iupac_scores_are_expected_scores() {
+        let matrix = SubstitutionMatrix::iupac(4, -2);
+        assert_eq!(matrix.score(b'A', b'A'), 4);
+        assert_eq!(matrix.score(b'A', b'C'), -2);
+        assert_eq!(matrix.score(b'R', b'A'), 1); //A or G against A: (4 - 2) / 2
+        assert_eq!(matrix.score(b'A', b'R'), 1);
+        assert_eq!(matrix.score(b'r', b'a'), 1);
+        assert_eq!(matrix.score(b'R', b'C'), -2);
+        assert_eq!(matrix.score(b'R', b'R'), 1); //AA, AG, GA, GG: (4 - 2 - 2 + 4) / 4
+        assert_eq!(matrix.score(b'S', b'W'), -2);
+        assert_eq!(matrix.score(b'B', b'S'), 0); //two shared bases out of six pairs: (8 - 8) / 6
+        assert_eq!(matrix.score(b'N', b'A'), 0);
+        assert_eq!(matrix.score(b'N', b'N'), 0);
+        assert_eq!(matrix.score(b'X', b'X'), 4);
+        assert_eq!(matrix.score(b'X', b'A'), -2);
+
+        let matrix = SubstitutionMatrix::iupac(5, -4);
+        assert_eq!(matrix.score(b'R', b'A'), 1); //0.5 rounds away from 0
+        assert_eq!(matrix.score(b'Y', b'A'), -4);
+        assert_eq!(matrix.score(b'B', b'S'), -1); //(10 - 16) / 6
+        assert_eq!(matrix.max_score(), 5);
+    }
+
+    #[test]
+    fn parses_a_small_ncbi_matrix() {
+        let text = "# a tiny matrix\n\n   A  C  *\nA  2 -1 -3\nC -1  3 -4\n*  -3 -4  1\n";
+        let matrix = SubstitutionMatrix::parse("tiny", text).unwrap();
+        assert_eq!((matrix.name.as_str(), matrix.max_score()), ("tiny", 3));
+        for (c1, c2, score) in [(b'A', b'A', 2), (b'A', b'C', -1), (b'C', b'A', -1), (b'C', b'C', 3)] {
+            assert_eq!(matrix.score(c1, c2), score);
+        }
+
+        // anything outside the matrix scores like '*'
+        assert_eq!((matrix.score(b'W', b'A'), matrix.score(b'A', b'W'), matrix.score(b'C', b'W')), (-3, -3, -4));
+
+        // lowercase scores like uppercase
+        assert_eq!((matrix.score(b'a', b'c'), matrix.score(b'a', b'A'), matrix.score(b'C', b'c')), (-1, 2, 3));
+    }
+
+    #[test]
+    fn without_a_star_unknown_characters_score_the_worst() {
+        let matrix = SubstitutionMatrix::parse("small", "  A C\nA 1 -2\nC -2 5\n").unwrap();
+        assert_eq!((matrix.score(b'A', b'Z'), matrix.score(b'Z', b'C'), matrix.score(b'Z', b'Z')), (-2, -2, -2));
+        assert_eq!(matrix.max_score(), 5);
+    }
+
+    #[test]
+    fn malformed_matrices_name_the_line() {
+        assert_eq!(SubstitutionMatrix::parse("m", "# header next\nA CC\nA 1 1\n").unwrap_err(),
+            "matrix m line 2: expected single characters in the header, found \"CC\"");
+        assert_eq!(SubstitutionMatrix::parse("m", "  A C\nA 1 -1\nC -1\n").unwrap_err(),
+            "matrix m line 3: expected 2 scores, found 1");
+        assert_eq!(SubstitutionMatrix::parse("m", "  A C\nA 1 x\n").unwrap_err(),
+            "matrix m line 2: \"x\" is not a score");
+        assert_eq!(SubstitutionMatrix::parse("m", "  A C\nAC 1 -1\n").unwrap_err(),
+            "matrix m line 2: expected a single character row label, found \"AC\"");
+        assert_eq!(SubstitutionMatrix::parse("m", "# nothing\n").unwrap_err(), "matrix m is empty");
+        assert_eq!(SubstitutionMatrix::parse("m", "  A C\n").unwrap_err(), "matrix m has no rows");
+    }
+
+    #[test]
+    fn builtin_matrices_parse_and_are_symmetric() {
+        for (name, _) in BUILTIN {
+            let matrix = SubstitutionMatrix::builtin(&name.to_lowercase()).unwrap();
+            assert_eq!(matrix.name, name);
+            for c1 in 0..=255u8 {
+                for c2 in 0..=255u8 {
+                    assert_eq!(matrix.score(c1, c2), matrix.score(c2, c1), "{} {} {}", name, c1 as char, c2 as char);
+                }
+            }
+        }
+        assert!(SubstitutionMatrix::builtin("BLOSUM99").is_none());
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt::Write;
+use crate::algorithms::{retrace_path, State};
+use crate::gaps::GapModel;
+use crate::phylogeny::Tree;
+use crate::structs::{Cell, Config, DistanceMatrix, Matrix, REAL_MIN};
+
+
+/// Profile scores are averages, so they're kept in hundredths to fit the integer affine cells
+const SCALE: i32 = 100;
+
+/// Width of each block of the Clustal and FASTA output
+const BLOCK_WIDTH: usize = 60;
+
+/// Groups of similar residues for the Clustal consensus line, ':' for strong and '.' for weak
+const STRONG_GROUPS: [&str; 9] = ["STA", "NEQK", "NHQK", "NDEQ", "QHRK", "MILV", "MILF", "HY", "FYW"];
+const WEAK_GROUPS: [&str; 11] = ["CSA", "ATV", "SAG", "STNK", "STPA", "SGND", "SNDEQK", "NDEQHK", "NEQHRK", "FVLIM", "HFY"];
+
+
+/// A set of strings aligned to each other
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub members: Vec<usize>, //which input string each row is
+    pub rows: Vec<Vec<u8>>, //all the same length, with '-' for gaps
+}
+impl Profile {
+
+    /// A profile of just one string
+    fn single(member: usize, string: &str) -> Self {
+        Profile { members: vec![member], rows: vec![string.as_bytes().to_vec()] }
+    }
+
+    /// Number of columns
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    /// How many rows hold each residue, for every column, ignoring gaps
+    fn frequencies(&self) -> Vec<Vec<(u8, i32)>> {
+        let mut columns = Vec::with_capacity(self.width());
+        let mut counts = [0usize; 256];
+        for col in 0..self.width() {
+            for row in &self.rows {
+                counts[row[col] as usize] += 1;
+            }
+            let mut column = Vec::new();
+            for (c, count) in counts.iter_mut().enumerate() {
+                if *count > 0 && c as u8 != b'-' {
+                    column.push((c as u8, *count as i32));
+                }
+                *count = 0;
+            }
+            columns.push(column);
+        }
+        columns
+    }
+}
+
+
+/// Turns pairwise alignment scores into guide tree distances, scaling each score by the smaller self-score
+pub fn guide_distances(names: &[String], scores: &Matrix<i32>) -> DistanceMatrix {
+    let mut distances = DistanceMatrix::new(names.to_vec());
+    for i in 0..names.len() {
+        for j in i+1..names.len() {
+            let best = *scores.index(i, i).min(scores.index(j, j));
+            let distance = if best > 0 {
+                (1.0 - *scores.index(i, j) as f64 / best as f64).max(0.0)
+            } else {
+                1.0
+            };
+            distances.set(i, j, distance);
+        }
+    }
+    distances
+}
+
+
+/// Progressively aligns the strings, joining profiles in the order of the guide tree.
+/// The rows come back in the same order as the strings. Fails if joining two profiles needs more than `memory` bytes.
+pub fn progressive(strings: &[&str], guide: &Tree, config: &Config, memory: usize) -> Result<Profile, String> {
+    if strings.is_empty() {
+        return Ok(Profile { members: Vec::new(), rows: Vec::new() });
+    }
+    let mut profile = align_subtree(strings, guide, guide.root, config, memory)?;
+
+    let mut order: Vec<usize> = (0..profile.members.len()).collect();
+    order.sort_by_key(|&row| profile.members[row]);
+    profile.rows = order.iter().map(|&row| std::mem::take(&mut profile.rows[row])).collect();
+    profile.members.sort();
+    Ok(profile)
+}
+fn align_subtree(strings: &[&str], guide: &Tree, node: usize, config: &Config, memory: usize) -> Result<Profile, String> {
+    let children = &guide.nodes[node].children;
+    if children.is_empty() { //the leaves come first in the tree, in the same order as the strings
+        return Ok(Profile::single(node, strings[node]));
+    }
+    let mut profile = align_subtree(strings, guide, children[0].0, config, memory)?;
+    for &(child, _) in &children[1..] {
+        let other = align_subtree(strings, guide, child, config, memory)?;
+        profile = align_profiles(&profile, &other, config, memory)?;
+    }
+    Ok(profile)
+}
+
+
+/// Bytes of the matrix for aligning profiles n and m columns wide
+pub fn bytes_needed(n: usize, m: usize) -> usize {
+    (n+1) * (m+1) * size_of::<Cell>()
+}
+
+
+/// Globally aligns two profiles under the affine gap model, scoring columns by their average pair score.
+/// Gaps are whole columns of gaps inserted into one profile, so earlier alignments are never broken up.
+/// Fails rather than fill a matrix of more than `memory` bytes.
+pub fn align_profiles(a: &Profile, b: &Profile, config: &Config, memory: usize) -> Result<Profile, String> {
+    let bytes = bytes_needed(a.width(), b.width());
+    if bytes > memory {
+        return Err(format!("Aligning profiles of {} and {} columns needs at least {} MB, more than --memory allows",
+            a.width(), b.width(), bytes.div_ceil(1024 * 1024)));
+    }
+    let scaled = Config {
+        true_match: 0,
+        mismatch: 0,
+        matrix: None,
+        h1: config.h1 * SCALE,
+        g1: config.g1 * SCALE,
+        h2: config.h2 * SCALE,
+        g2: config.g2 * SCALE,
+        gap_track: None,
+        gap_model: GapModel::Affine,
+        ignore_case: false,
+        mask_seeds: false,
+        s1_name: String::new(),
+        s2_name: String::new(),
+    };
+    let a_columns = a.frequencies();
+    let b_columns = b.frequencies();
+    let pairs = (a.rows.len() * b.rows.len()) as i64;
+    let column_score = |x: &[(u8, i32)], y: &[(u8, i32)]| -> i32 {
+        let mut score: i64 = 0;
+        for &(c1, n1) in x {
+            for &(c2, n2) in y {
+                score += (n1 * n2 * config.substitution(c1, c2)) as i64;
+            }
+        }
+        (score * SCALE as i64 / pairs) as i32
+    };
+
+    let n = a.width();
+    let m = b.width();
+    let mut matrix: Matrix<Cell> = Matrix::with_shape(n+1, m+1);
+    for i in 1..n+1 {
+        *matrix.index_mut(i, 0) = Cell { d_score: scaled.h2 + scaled.g2 * i as i32, i_score: REAL_MIN, s_score: REAL_MIN };
+    }
+    for j in 1..m+1 {
+        *matrix.index_mut(0, j) = Cell { d_score: REAL_MIN, i_score: scaled.h1 + scaled.g1 * j as i32, s_score: REAL_MIN };
+    }
+    for i in 1..n+1 {
+        for j in 1..m+1 {
+            let match_score = column_score(&a_columns[i-1], &b_columns[j-1]);
+            *matrix.index_mut(i, j) = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), match_score, &scaled, i);
+        }
+    }
+
+    // lay both profiles out along the path
+    let (path, _, _) = retrace_path(|i, j| matrix.index(i, j), &scaled, n, m, false);
+    let mut rows: Vec<Vec<u8>> = vec![Vec::with_capacity(path.len()); a.rows.len() + b.rows.len()];
+    let (mut i, mut j) = (0, 0);
+    for state in path {
+        let (a_col, b_col) = match state {
+            State::S => (Some(i), Some(j)),
+            State::D => (Some(i), None),
+            State::I => (None, Some(j)),
+        };
+        for (row, source) in rows.iter_mut().zip(&a.rows) {
+            row.push(a_col.map_or(b'-', |col| source[col]));
+        }
+        for (row, source) in rows[a.rows.len()..].iter_mut().zip(&b.rows) {
+            row.push(b_col.map_or(b'-', |col| source[col]));
+        }
+        i += a_col.is_some() as usize;
+        j += b_col.is_some() as usize;
+    }
+
+    let mut members = a.members.clone();
+    members.extend(&b.members);
+    Ok(Profile { members, rows })
+}
+
+
+/// Clustal's conservation line: '*' for identical columns, ':' and '.' for strongly and weakly similar ones
+pub fn conservation(profile: &Profile) -> String {
+    let mut line = String::with_capacity(profile.width());
+    for col in 0..profile.width() {
+        let column: Vec<u8> = profile.rows.iter().map(|row| row[col].to_ascii_uppercase()).collect();
+        let in_group = |group: &&str| column.iter().all(|c| group.as_bytes().contains(c));
+        line.push(if column.contains(&b'-') {
+            ' '
+        } else if column.iter().all(|&c| c == column[0]) {
+            '*'
+        } else if STRONG_GROUPS.iter().any(in_group) {
+            ':'
+        } else if WEAK_GROUPS.iter().any(in_group) {
+            '.'
+        } else {
+            ' '
+        });
+    }
+    line
+}
+
+
+/// Majority consensus, with a gap wherever gaps outnumber every residue
+pub fn consensus(profile: &Profile) -> String {
+    let mut line = String::with_capacity(profile.width());
+    let mut counts = [0usize; 256];
+    for col in 0..profile.width() {
+        counts.fill(0);
+        for row in &profile.rows {
+            counts[row[col].to_ascii_uppercase() as usize] += 1;
+        }
+        let mut best = b'-';
+        for c in (0..256).filter(|&c| c != b'-' as usize) { //ties go to the first residue
+            if counts[c] > 0 && (best == b'-' || counts[c] > counts[best as usize]) {
+                best = c as u8;
+            }
+        }
+        if counts[b'-' as usize] > counts[best as usize] {
+            best = b'-';
+        }
+        line.push(best as char);
+    }
+    line
+}
+
+
+/// Writes the alignment in Clustal format, with each row's residue count at the end of every block
+pub fn to_clustal(names: &[String], profile: &Profile) -> String {
+    let labels: Vec<&str> = profile.members.iter()
+        .map(|&member| names[member].split_whitespace().next().unwrap_or(""))
+        .collect();
+    let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0) + 6;
+    let conservation = conservation(profile);
+
+    let mut out = String::from("CLUSTAL multiple sequence alignment\n\n\n");
+    let mut counts = vec![0; profile.rows.len()];
+    let mut block_start = 0;
+    while block_start < profile.width() {
+        let block_end = (block_start + BLOCK_WIDTH).min(profile.width());
+        for (k, row) in profile.rows.iter().enumerate() {
+            let block = &row[block_start..block_end];
+            counts[k] += block.iter().filter(|&&c| c != b'-').count();
+            writeln!(out, "{:<width$}{} {}", labels[k], String::from_utf8_lossy(block), counts[k], width = label_width).unwrap();
+        }
+        writeln!(out, "{:<width$}{}", "", &conservation[block_start..block_end], width = label_width).unwrap();
+        writeln!(out).unwrap();
+        block_start = block_end;
+    }
+    out
+}
+
+
+/// Writes the gapped rows as FASTA records under their full names, followed by a consensus record
+pub fn to_aligned_fasta(names: &[String], profile: &Profile) -> String {
+    let mut out = String::new();
+    let records = profile.members.iter()
+        .zip(&profile.rows)
+        .map(|(&member, row)| (names[member].as_str(), row.clone()))
+        .chain([("consensus", consensus(profile).into_bytes())]);
+    for (name, row) in records {
+        writeln!(out, ">{}", name).unwrap();
+        for line in row.chunks(BLOCK_WIDTH) {
+            writeln!(out, "{}", String::from_utf8_lossy(line)).unwrap();
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::needleman_wunsch;
+    use crate::phylogeny::upgma;
+    use crate::random::Rng;
+    use crate::testing::{config, random_string};
+
+    /// The guide tree main builds, from the pairwise global scores
+    fn guide(strings: &[&str], config: &Config) -> Tree {
+        let names: Vec<String> = (0..strings.len()).map(|k| format!("s{}", k)).collect();
+        let mut scores: Matrix<i32> = Matrix::with_shape(strings.len(), strings.len());
+        for i in 0..strings.len() {
+            for j in 0..strings.len() {
+                *scores.index_mut(i, j) = needleman_wunsch(strings[i], strings[j], config).score;
+            }
+        }
+        upgma(&guide_distances(&names, &scores))
+    }
+
+    #[test]
+    fn progressive_keeps_every_string_in_order() {
+        let config = config("match = 2\nmismatch = -3\nh = -5\ng = -2\n");
+        let mut rng = Rng::new(51);
+        for count in 1..7 {
+            for _ in 0..10 {
+                let strings: Vec<String> = (0..count).map(|_| {
+                    let len = 1 + rng.below(25);
+                    random_string(&mut rng, b"ACGT", len)
+                }).collect();
+                let strings: Vec<&str> = strings.iter().map(String::as_str).collect();
+                let profile = progressive(&strings, &guide(&strings, &config), &config, usize::MAX).unwrap();
+                assert_eq!(profile.members, (0..count).collect::<Vec<usize>>());
+                for (row, string) in profile.rows.iter().zip(&strings) {
+                    assert_eq!(row.len(), profile.width());
+                    assert_eq!(String::from_utf8_lossy(row).replace('-', ""), *string);
+                }
+            }
+        }
+        assert!(progressive(&[], &guide(&[], &config), &config, 0).unwrap().rows.is_empty());
+    }
+
+    #[test]
+    fn profiles_too_big_for_memory_are_refused() {
+        let config = config("");
+        let strings = ["ACGTACGT", "ACGAACGT", "TCGTACG"];
+        let guide = guide(&strings, &config);
+        assert!(progressive(&strings, &guide, &config, bytes_needed(8, 8)).is_ok());
+        assert_eq!(progressive(&strings, &guide, &config, bytes_needed(8, 8) - 1).unwrap_err(),
+            "Aligning profiles of 8 and 8 columns needs at least 1 MB, more than --memory allows");
+    }
+
+    #[test]
+    fn clustal_and_fasta_layout() {
+        let names = vec![String::from("first sequence"), String::from("second")];
+        let profile = Profile {
+            members: vec![0, 1],
+            rows: vec![format!("{}{}", "ACGT".repeat(16), "A-C").into_bytes(), format!("{}{}", "ACGA".repeat(16), "-GC").into_bytes()],
+        };
+        let clustal = [
+            "CLUSTAL multiple sequence alignment",
+            "",
+            "",
+            "first       ACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT 60",
+            "second      ACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGA 60",
+            "            ***:***:***:***:***:***:***:***:***:***:***:***:***:***:***:",
+            "",
+            "first       ACGTA-C 66",
+            "second      ACGA-GC 66",
+            "            ***:  *",
+            "",
+            "",
+        ];
+        assert_eq!(to_clustal(&names, &profile), clustal.join("\n"));
+        let fasta = [
+            ">first sequence",
+            "ACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT",
+            "ACGTA-C",
+            ">second",
+            "ACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGA",
+            "ACGA-GC",
+            ">consensus",
+            "ACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGAACGA",
+            "ACGAAGC", //A against T is a tie, which goes to the first residue
+            "",
+        ];
+        assert_eq!(to_aligned_fasta(&names, &profile), fasta.join("\n"));
+    }
+
+    #[test]
+    fn consensus_takes_gaps_only_when_they_outnumber_every_residue() {
+        let profile = Profile {
+            members: vec![0, 1, 2, 3],
+            rows: ["A-CGT-", "A-C-TT", "a--GG-", "T-G-GA"].iter().map(|row| row.as_bytes().to_vec()).collect(),
+        };
+        assert_eq!(consensus(&profile), "A-CGG-");
+    }
+}
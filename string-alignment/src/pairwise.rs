@@ -0,0 +1,184 @@
+use std::{fmt::{Display, Write}, thread};
+use crate::structs::{Alignment, Config, DistanceMatrix, Matrix};
+
+
+/// Scores, identities and distances from aligning every pair of strings
+#[derive(Debug)]
+pub struct PairwiseMatrices {
+    pub scores: Matrix<i32>,
+    pub identities: Matrix<f64>, //percent of alignment columns that match
+    pub distances: DistanceMatrix, //1 - identity, as a fraction
+}
+
+
+/// Aligns every pair of strings with `align`, spreading the pairs over `threads` threads
+pub fn all_vs_all<F>(names: &[String], strings: &[&str], config: &Config, threads: usize, align: F) -> PairwiseMatrices
+where
+    F: Fn(&str, &str) -> Alignment + Sync,
+{
+    let count = strings.len();
+    let mut pairs: Vec<(usize, usize)> = Vec::with_capacity(count * count / 2);
+    for i in 0..count {
+        for j in i+1..count {
+            pairs.push((i, j));
+        }
+    }
+
+    // each thread takes every threads-th pair
+    let threads = threads.max(1);
+    let align = &align;
+    let pairs = &pairs;
+    let results: Vec<(usize, usize, i32, f64)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|t| {
+            scope.spawn(move || {
+                pairs.iter()
+                    .skip(t)
+                    .step_by(threads)
+                    .map(|&(i, j)| {
+                        let alignment = align(strings[i], strings[j]);
+                        (i, j, alignment.score, alignment.identity())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        }).collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("Alignment thread panicked")).collect()
+    });
+
+    let mut matrices = PairwiseMatrices {
+        scores: Matrix::with_shape(count, count),
+        identities: Matrix::with_shape(count, count),
+        distances: DistanceMatrix::new(names.to_vec()),
+    };
+
+    // a string against itself is a perfect match, so only its score needs working out
+    for (i, string) in strings.iter().enumerate() {
+        *matrices.scores.index_mut(i, i) = string.bytes().map(|c| config.substitution(c, c)).sum();
+        *matrices.identities.index_mut(i, i) = 100.0;
+    }
+    for (i, j, score, identity) in results {
+        *matrices.scores.index_mut(i, j) = score;
+        *matrices.scores.index_mut(j, i) = score;
+        *matrices.identities.index_mut(i, j) = identity;
+        *matrices.identities.index_mut(j, i) = identity;
+        matrices.distances.set(i, j, 1.0 - identity / 100.0);
+    }
+    matrices
+}
+
+
+/// Writes a square matrix as tab separated values, with the names along the top and the left
+pub fn to_tsv<T: Default + Clone + Display>(names: &[String], matrix: &Matrix<T>) -> String {
+    let mut out = String::new();
+    for name in names {
+        write!(out, "\t{}", name).unwrap();
+    }
+    writeln!(out).unwrap();
+    for (i, name) in names.iter().enumerate() {
+        write!(out, "{}", name).unwrap();
+        for j in 0..names.len() {
+            write!(out, "\t{}", matrix.index(i, j)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+
+/// Writes a distance matrix in PHYLIP's square format.
+/// Labels are the first word of each name cut to PHYLIP's 10 characters, followed by a space for relaxed readers.
+/// Labels that come out the same as an earlier one end in a number instead, so every row can still be told apart.
+pub fn to_phylip(distances: &DistanceMatrix) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", distances.len()).unwrap();
+    for (i, label) in phylip_labels(&distances.names).iter().enumerate() {
+        write!(out, "{:<10}", label).unwrap();
+        for j in 0..distances.len() {
+            write!(out, " {:.6}", distances.get(i, j)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+
+/// Unique labels of at most 10 characters for the names. A label already taken keeps as much of its start as fits
+/// before "_2", "_3" and so on, taking the first number no other label has.
+fn phylip_labels(names: &[String]) -> Vec<String> {
+    let cut: Vec<String> = names.iter()
+        .map(|name| name.split_whitespace().next().unwrap_or("").chars().take(10).collect())
+        .collect();
+    let mut labels: Vec<String> = Vec::with_capacity(cut.len());
+    for label in &cut {
+        let mut unique = label.clone();
+        let mut number = 2;
+        while labels.contains(&unique) || (unique != *label && cut.contains(&unique)) {
+            let suffix = format!("_{}", number);
+            unique = label.chars().take(10usize.saturating_sub(suffix.len())).collect::<String>() + &suffix;
+            number += 1;
+        }
+        labels.push(unique);
+    }
+    labels
+}
+
+
+/// Reads a distance matrix in PHYLIP's square or lower-triangular format.
+/// Each row starts with a label of one word, which is enough for both strict files without spaces and relaxed ones.
+pub fn from_phylip(text: &str) -> Result<DistanceMatrix, String> {
+    let mut words = text.split_ascii_whitespace();
+    let count: usize = match words.next() {
+        None => return Err(String::from("distance matrix is empty")),
+        Some(word) => word.parse().map_err(|_| format!("expected the number of strings, found \"{}\"", word))?,
+    };
+
+    // rows are read word by word since long ones may wrap onto several lines
+    let words: Vec<&str> = words.collect();
+    let square = words.len() == count * (count + 1);
+    let triangular = words.len() == count + count * count.saturating_sub(1) / 2;
+    if count > 0 && !square && !triangular {
+        return Err(format!("expected a square or lower-triangular matrix of {} strings", count));
+    }
+
+    let mut names: Vec<String> = Vec::with_capacity(count);
+    let mut values: Vec<Vec<f64>> = Vec::with_capacity(count);
+    let mut at = 0;
+    for i in 0..count {
+        names.push(String::from(words[at]));
+        let width = if square {count} else {i};
+        let mut row = Vec::with_capacity(width);
+        for word in &words[at+1..at+1+width] {
+            row.push(word.parse::<f64>().map_err(|_| format!("row {}: \"{}\" is not a distance", i+1, word))?);
+        }
+        values.push(row);
+        at += width + 1;
+    }
+
+    let mut distances = DistanceMatrix::new(names);
+    for (i, row) in values.iter().enumerate() {
+        for (j, &distance) in row.iter().enumerate().take(i) {
+            distances.set(i, j, distance);
+        }
+    }
+    Ok(distances)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phylip_labels_are_unique() {
+        let names: Vec<String> = ["Homo_sapiens_1 first", "Homo_sapiens_2", "Homo_sap_2", "Homo_sapie", "Mus", "Mus"]
+            .iter().map(|&name| String::from(name)).collect();
+        assert_eq!(phylip_labels(&names), ["Homo_sapie", "Homo_sap_3", "Homo_sap_2", "Homo_sap_4", "Mus", "Mus_2"]);
+
+        let mut distances = DistanceMatrix::new(names);
+        distances.set(0, 1, 0.25);
+        let text = to_phylip(&distances);
+        assert!(text.starts_with("6\nHomo_sapie 0.000000 0.250000"));
+        let read = from_phylip(&text).unwrap();
+        assert_eq!(read.names, ["Homo_sapie", "Homo_sap_3", "Homo_sap_2", "Homo_sap_4", "Mus", "Mus_2"]);
+        assert_eq!(read.get(1, 0), 0.25);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::structs::DistanceMatrix;
+
+
+/// One node of a phylogenetic tree, leaves carry the string's name
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub name: Option<String>,
+    pub children: Vec<(usize, f64)>, //index of each child along with its branch length
+}
+
+
+/// A phylogenetic tree stored as a list of nodes
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub nodes: Vec<TreeNode>,
+    pub root: usize,
+}
+impl Tree {
+
+    /// Starts a tree with one leaf per name, leaving the caller to join them
+    fn with_leaves(names: &[String]) -> Self {
+        Tree {
+            nodes: names.iter().map(|name| TreeNode { name: Some(name.clone()), children: Vec::new() }).collect(),
+            root: 0,
+        }
+    }
+
+    /// Adds an internal node above the given children, returning its index
+    fn join(&mut self, children: Vec<(usize, f64)>) -> usize {
+        self.nodes.push(TreeNode { name: None, children });
+        self.nodes.len() - 1
+    }
+
+    /// Writes the tree in Newick format
+    pub fn to_newick(&self) -> String {
+        let mut out = String::new();
+        self.newick_recursive(self.root, &mut out);
+        out.push(';');
+        out
+    }
+    fn newick_recursive(&self, node: usize, out: &mut String) {
+        let node = &self.nodes[node];
+        if !node.children.is_empty() {
+            out.push('(');
+            for (k, &(child, length)) in node.children.iter().enumerate() {
+                if k > 0 {
+                    out.push(',');
+                }
+                self.newick_recursive(child, out);
+                out.push_str(&format!(":{:.6}", length));
+            }
+            out.push(')');
+        }
+        if let Some(name) = &node.name {
+            out.push_str(&newick_label(name));
+        }
+    }
+}
+
+
+/// Quotes a label if it has any characters Newick gives a meaning to
+fn newick_label(name: &str) -> String {
+    if name.chars().any(|c| "()[]':;,".contains(c) || c.is_whitespace()) {
+        format!("'{}'", name.replace('\'', "''"))
+    } else {
+        String::from(name)
+    }
+}
+
+
+/// Builds a rooted, ultrametric tree by UPGMA (average linkage clustering)
+pub fn upgma(distances: &DistanceMatrix) -> Tree {
+    let mut tree = Tree::with_leaves(&distances.names);
+    if distances.is_empty() {
+        return tree;
+    }
+
+    // every active cluster has a node, a size and a height, and distances to the other clusters
+    let mut active: Vec<usize> = (0..distances.len()).collect();
+    let mut sizes: Vec<usize> = vec![1; distances.len()];
+    let mut heights: Vec<f64> = vec![0.0; distances.len()];
+    let mut dist: Vec<Vec<f64>> = (0..distances.len())
+        .map(|i| (0..distances.len()).map(|j| distances.get(i, j)).collect())
+        .collect();
+
+    while active.len() > 1 {
+        // find the closest pair of clusters
+        let (mut best_a, mut best_b) = (0, 1);
+        for a in 0..active.len() {
+            for b in a+1..active.len() {
+                if dist[active[a]][active[b]] < dist[active[best_a]][active[best_b]] {
+                    best_a = a;
+                    best_b = b;
+                }
+            }
+        }
+        let (x, y) = (active[best_a], active[best_b]);
+        let height = dist[x][y] / 2.0;
+
+        // merge them under a new node at half their distance
+        let node = tree.join(vec![
+            (x, (height - heights[x]).max(0.0)),
+            (y, (height - heights[y]).max(0.0)),
+        ]);
+        sizes.push(sizes[x] + sizes[y]);
+        heights.push(height);
+        let mut row: Vec<f64> = Vec::with_capacity(node+1);
+        for other in dist.iter_mut() {
+            let merged = (other[x] * sizes[x] as f64 + other[y] * sizes[y] as f64) / (sizes[x] + sizes[y]) as f64;
+            other.push(merged);
+            row.push(merged);
+        }
+        row.push(0.0);
+        dist.push(row);
+
+        active.remove(best_b); //best_b > best_a, so remove it first
+        active.remove(best_a);
+        active.push(node);
+    }
+
+    tree.root = active[0];
+    tree
+}
+
+
+/// Builds an unrooted tree by neighbor-joining, written with a three-way split at the root
+pub fn neighbor_joining(distances: &DistanceMatrix) -> Tree {
+    let mut tree = Tree::with_leaves(&distances.names);
+    let mut active: Vec<usize> = (0..distances.len()).collect();
+    let mut dist: Vec<Vec<f64>> = (0..distances.len())
+        .map(|i| (0..distances.len()).map(|j| distances.get(i, j)).collect())
+        .collect();
+
+    while active.len() > 3 {
+        let r = active.len() as f64;
+        let sums: Vec<f64> = active.iter()
+            .map(|&i| active.iter().map(|&k| dist[i][k]).sum())
+            .collect();
+
+        // pick the pair minimizing the Q criterion
+        let (mut best_a, mut best_b) = (0, 1);
+        let mut best_q = f64::INFINITY;
+        for a in 0..active.len() {
+            for b in a+1..active.len() {
+                let q = (r - 2.0) * dist[active[a]][active[b]] - sums[a] - sums[b];
+                if q < best_q {
+                    best_q = q;
+                    best_a = a;
+                    best_b = b;
+                }
+            }
+        }
+        let (x, y) = (active[best_a], active[best_b]);
+        let to_x = dist[x][y] / 2.0 + (sums[best_a] - sums[best_b]) / (2.0 * (r - 2.0));
+        let to_y = dist[x][y] - to_x;
+
+        // join them, negative branch lengths are set to 0
+        let node = tree.join(vec![(x, to_x.max(0.0)), (y, to_y.max(0.0))]);
+        let between = dist[x][y];
+        let mut row: Vec<f64> = Vec::with_capacity(node+1);
+        for other in dist.iter_mut() {
+            let merged = (other[x] + other[y] - between) / 2.0;
+            other.push(merged);
+            row.push(merged);
+        }
+        row.push(0.0);
+        dist.push(row);
+
+        active.remove(best_b);
+        active.remove(best_a);
+        active.push(node);
+    }
+
+    // the last two or three nodes hang off the root
+    tree.root = match active.len() {
+        0 => return tree,
+        1 => active[0],
+        2 => {
+            let half = dist[active[0]][active[1]] / 2.0;
+            tree.join(vec![(active[0], half), (active[1], half)])
+        },
+        _ => {
+            let (a, b, c) = (active[0], active[1], active[2]);
+            tree.join(vec![
+                (a, ((dist[a][b] + dist[a][c] - dist[b][c]) / 2.0).max(0.0)),
+                (b, ((dist[a][b] + dist[b][c] - dist[a][c]) / 2.0).max(0.0)),
+                (c, ((dist[a][c] + dist[b][c] - dist[a][b]) / 2.0).max(0.0)),
+            ])
+        },
+    };
+    tree
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distances(names: &str, rows: &[[f64; 5]]) -> DistanceMatrix {
+        let mut matrix = DistanceMatrix::new(names.chars().map(String::from).collect());
+        for (i, row) in rows.iter().enumerate() {
+            for (j, &distance) in row.iter().enumerate() {
+                matrix.set(i, j, distance);
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn upgma_known_tree() {
+        // the 5S ribosomal RNA example of the UPGMA article on Wikipedia
+        let matrix = distances("abcde", &[
+            [0.0, 17.0, 21.0, 31.0, 23.0],
+            [17.0, 0.0, 30.0, 34.0, 21.0],
+            [21.0, 30.0, 0.0, 28.0, 39.0],
+            [31.0, 34.0, 28.0, 0.0, 43.0],
+            [23.0, 21.0, 39.0, 43.0, 0.0],
+        ]);
+        assert_eq!(upgma(&matrix).to_newick(), "((e:11.000000,(a:8.500000,b:8.500000):2.500000):5.500000,(c:14.000000,d:14.000000):2.500000);");
+    }
+
+    #[test]
+    fn neighbor_joining_known_tree() {
+        // the worked example of the neighbor joining article on Wikipedia
+        let matrix = distances("abcde", &[
+            [0.0, 5.0, 9.0, 9.0, 8.0],
+            [5.0, 0.0, 10.0, 10.0, 9.0],
+            [9.0, 10.0, 0.0, 8.0, 7.0],
+            [9.0, 10.0, 8.0, 0.0, 3.0],
+            [8.0, 9.0, 7.0, 3.0, 0.0],
+        ]);
+        assert_eq!(neighbor_joining(&matrix).to_newick(), "(d:2.000000,e:1.000000,(c:4.000000,(a:2.000000,b:3.000000):3.000000):2.000000);");
+    }
+}
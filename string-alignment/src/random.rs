@@ -0,0 +1,175 @@
+/// Seed used unless one is given, so shuffled runs can be repeated
+pub const DEFAULT_SEED: u64 = 0x9e3779b97f4a7c15;
+
+
+/// What a shuffle keeps from the original string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shuffle {
+    Mono, //the count of every character
+    Di, //the count of every pair of neighbouring characters, and so also the first and last characters
+}
+impl Shuffle {
+
+    /// Parses "mono" or "di"
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "mono" => Ok(Shuffle::Mono),
+            "di" => Ok(Shuffle::Di),
+            _ => Err(format!("unknown shuffle \"{}\", expected mono or di", name)),
+        }
+    }
+
+    /// Name to show in the output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shuffle::Mono => "mononucleotide",
+            Shuffle::Di => "dinucleotide",
+        }
+    }
+}
+
+
+/// Small xorshift64* generator, good enough for shuffling strings
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed.max(1) } //xorshift never leaves 0
+    }
+
+    /// Next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Uniform number in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * n as f64) as usize
+    }
+
+    /// Fisher-Yates shuffle in place
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for k in (1..items.len()).rev() {
+            items.swap(k, self.below(k+1));
+        }
+    }
+
+    /// Returns a shuffled copy of the string, preserving what `kind` asks for
+    pub fn shuffle_string(&mut self, s: &[u8], kind: Shuffle) -> Vec<u8> {
+        match kind {
+            Shuffle::Mono => {
+                let mut shuffled = s.to_vec();
+                self.shuffle(&mut shuffled);
+                shuffled
+            },
+            Shuffle::Di => self.shuffle_pairs(s),
+        }
+    }
+
+    /// Altschul-Erickson shuffle: a random Eulerian walk through the graph whose edges are the string's neighbouring pairs.
+    /// Each character's last edge out is picked so that they form a tree into the final character, which guarantees the
+    /// walk can use every edge, and the rest of its edges are visited in random order.
+    fn shuffle_pairs(&mut self, s: &[u8]) -> Vec<u8> {
+        if s.len() < 3 {
+            return s.to_vec();
+        }
+        let mut edges: Vec<Vec<u8>> = vec![Vec::new(); 256];
+        for pair in s.windows(2) {
+            edges[pair[0] as usize].push(pair[1]);
+        }
+        let end = s[s.len()-1];
+        let vertices: Vec<usize> = (0..256).filter(|&c| !edges[c].is_empty() && c != end as usize).collect();
+
+        // pick last edges until following them from every character leads to the end
+        let mut last: Vec<usize> = vec![0; 256];
+        loop {
+            for &v in &vertices {
+                last[v] = self.below(edges[v].len());
+            }
+            let reaches_end = vertices.iter().all(|&start| {
+                let mut v = start;
+                for _ in 0..vertices.len() {
+                    v = edges[v][last[v]] as usize;
+                    if v == end as usize {
+                        return true;
+                    }
+                }
+                false
+            });
+            if reaches_end {
+                break;
+            }
+        }
+
+        // shuffle everything but the last edge out of each character, then walk
+        for &v in &vertices {
+            let chosen = last[v];
+            let len = edges[v].len();
+            edges[v].swap(chosen, len-1);
+            self.shuffle(&mut edges[v][..len-1]);
+        }
+        if !edges[end as usize].is_empty() {
+            self.shuffle(&mut edges[end as usize]);
+        }
+        let mut next = vec![0usize; 256];
+        let mut shuffled = Vec::with_capacity(s.len());
+        let mut v = s[0];
+        shuffled.push(v);
+        for _ in 1..s.len() {
+            let out = edges[v as usize][next[v as usize]];
+            next[v as usize] += 1;
+            shuffled.push(out);
+            v = out;
+        }
+        shuffled
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::random_string;
+
+    /// How many times each pair of neighbouring characters appears
+    fn pair_counts(s: &[u8]) -> Vec<usize> {
+        let mut counts = vec![0; 256*256];
+        s.windows(2).for_each(|pair| counts[pair[0] as usize * 256 + pair[1] as usize] += 1);
+        counts
+    }
+
+    #[test]
+    fn dinucleotide_shuffles_keep_pairs_and_ends() {
+        let mut rng = Rng::new(31);
+        for len in [0, 1, 2, 3, 10, 200] {
+            for _ in 0..50 {
+                let s = random_string(&mut rng, b"ACGT", len);
+                let shuffled = rng.shuffle_string(s.as_bytes(), Shuffle::Di);
+                assert_eq!(pair_counts(&shuffled), pair_counts(s.as_bytes()), "{}", s);
+                assert_eq!((shuffled.first(), shuffled.last()), (s.as_bytes().first(), s.as_bytes().last()), "{}", s);
+            }
+        }
+
+        // a walk with more than one route actually gets shuffled
+        let s = b"ACGTTGCAACGTAGCTTCGA";
+        assert!((0..20).any(|_| rng.shuffle_string(s, Shuffle::Di) != s));
+    }
+
+    #[test]
+    fn mononucleotide_shuffles_keep_composition() {
+        let mut rng = Rng::new(32);
+        for _ in 0..50 {
+            let s = random_string(&mut rng, b"ACGT", 100);
+            let mut shuffled = rng.shuffle_string(s.as_bytes(), Shuffle::Mono);
+            let mut sorted = s.into_bytes();
+            shuffled.sort_unstable();
+            sorted.sort_unstable();
+            assert_eq!(shuffled, sorted);
+        }
+    }
+}
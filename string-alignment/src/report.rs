@@ -0,0 +1,253 @@
+use std::fmt::Write;
+use crate::codon::CodonReport;
+use crate::gaps::GAP_CLASSES;
+use crate::stats::{KarlinAltschul, ShuffleTest};
+use crate::structs::{Alignment, Config, Strand};
+use crate::translated::TranslatedAlignment;
+
+
+/// Width of each chunk of the rendered alignment
+const CHUNK_WIDTH: usize = 60;
+
+
+/// Renders an alignment as chunked rows followed by the report
+pub fn render_alignment(alignment: &Alignment, config: &Config) -> String {
+    let mut out = String::new();
+
+    // s1       1    AC
+    // string2  2
+    // establish the headers here (the names of the string sequences)
+    let mut s1_header = config.s1_name.clone() + "  ";
+    let mut s2_header = config.s2_name.clone() + "  ";
+
+    // pad the shorter header
+    if s1_header.len() > s2_header.len() {
+        s2_header += &" ".repeat(s1_header.len() - s2_header.len());
+    } else {
+        s1_header += &" ".repeat(s2_header.len() - s1_header.len());
+    }
+
+    let ma_header = " ".repeat(s1_header.len());
+
+    let max_num_len: usize = 2 + alignment.s1_end.max(alignment.s2_end).to_string().len();
+    let mut s1_counter = alignment.s1_start;
+    let mut s2_counter = 0; //characters of s2 shown so far, which are numbered backwards on the reverse strand
+    let s2_position = |counter: usize| match alignment.strand { //1-based position of the next character of s2
+        Strand::Forward => alignment.s2_start + counter + 1,
+        Strand::Reverse => alignment.s2_end - counter,
+    };
+
+    // print the retrace, one chunk at a time
+    let mut chunk_start = 0;
+    while chunk_start < alignment.len() {
+        let chunk_end = (chunk_start + CHUNK_WIDTH).min(alignment.len());
+        let s1_chunk = &alignment.s1_row[chunk_start..chunk_end]; //the chunk we're printing now
+        let s2_chunk = &alignment.s2_row[chunk_start..chunk_end];
+        let ma_chunk = &alignment.ma_row[chunk_start..chunk_end];
+
+        let s1_num_len = (s1_counter+1).to_string().len(); //stringify the current location
+        let s2_num_len = s2_position(s2_counter).to_string().len();
+
+        let s1_counter_next = s1_counter + s1_chunk.len() - s1_chunk.matches('-').count(); //calculate the new location
+        let s2_counter_next = s2_counter + s2_chunk.len() - s2_chunk.matches('-').count();
+
+        writeln!(out, "{}{}{}{}  {}", s1_header, s1_counter+1, " ".repeat(max_num_len-s1_num_len), s1_chunk, s1_counter_next).unwrap();
+        writeln!(out, "{}{}{}", ma_header, " ".repeat(max_num_len), ma_chunk).unwrap();
+        let s2_last = match alignment.strand { //position of the last character of s2 in this chunk
+            Strand::Forward => alignment.s2_start + s2_counter_next,
+            Strand::Reverse => alignment.s2_end + 1 - s2_counter_next,
+        };
+        writeln!(out, "{}{}{}{}  {}", s2_header, s2_position(s2_counter), " ".repeat(max_num_len-s2_num_len), s2_chunk, s2_last).unwrap();
+        if chunk_end - chunk_start == CHUNK_WIDTH { //only full chunks are followed by a blank line
+            writeln!(out).unwrap();
+        }
+
+        s1_counter = s1_counter_next; //update the current location
+        s2_counter = s2_counter_next;
+        chunk_start = chunk_end;
+    }
+
+    out += &render_report(alignment);
+    out
+}
+
+
+/// Renders the score and statistics of an alignment
+pub fn render_report(alignment: &Alignment) -> String {
+    let mut out = String::new();
+    writeln!(out, "\n\n").unwrap();
+    writeln!(out, "Report:\n").unwrap();
+    writeln!(out, "{} optimal score = {}\n", alignment.kind.name(), alignment.score).unwrap();
+    if alignment.strand == Strand::Reverse {
+        writeln!(out, "Strand = Plus/Minus, s2 was reverse complemented\n").unwrap();
+    }
+    writeln!(out, "Number of:  matches = {}, mismatches = {}, opening gaps = {}, gap extensions = {}\n",
+        alignment.matches, alignment.mismatches, alignment.gap_opens, alignment.gap_extensions).unwrap();
+    if alignment.ambiguous > 0 {
+        writeln!(out, "Ambiguous = {}/{} ({}%), pairs with an ambiguity code, counted apart from the matches and mismatches\n",
+            alignment.ambiguous, alignment.len(), (alignment.ambiguous as f64 / alignment.len() as f64 * 100.0) as i32).unwrap();
+    }
+    writeln!(out, "Identities = {}/{} ({}%), Gaps = {}/{} ({}%)",
+        alignment.matches, alignment.len(), alignment.identity() as i32,
+        alignment.gap_extensions, alignment.len(), alignment.gap_percent() as i32).unwrap();
+    if alignment.gap_opens > 0 {
+        writeln!(out, "\nGap lengths:  {}", render_gap_classes(&alignment.gap_lengths())).unwrap();
+    }
+    out
+}
+
+
+/// Renders how many gaps fall into each length class, e.g. "1: 3, 2-5: 1, 6-20: 0, 21-100: 0, >100: 1"
+fn render_gap_classes(lengths: &[usize]) -> String {
+    let mut classes: Vec<String> = Vec::with_capacity(GAP_CLASSES.len() + 1);
+    let mut shortest = 1;
+    for &longest in &GAP_CLASSES {
+        let count = lengths.iter().filter(|&&length| length >= shortest && length <= longest).count();
+        let label = if shortest == longest {longest.to_string()} else {format!("{}-{}", shortest, longest)};
+        classes.push(format!("{}: {}", label, count));
+        shortest = longest + 1;
+    }
+    let longer = lengths.iter().filter(|&&length| length >= shortest).count();
+    classes.push(format!(">{}: {}", shortest - 1, longer));
+    classes.join(", ")
+}
+
+
+/// Renders the Karlin-Altschul parameters a set of local alignments is judged by
+pub fn render_parameters(stats: &KarlinAltschul) -> String {
+    let source = if stats.gapped {"gapped, from shuffles"} else {"ungapped, ignoring gaps"};
+    match stats.h {
+        Some(h) => format!("Statistics ({}):  lambda = {:.4}, K = {:.4}, H = {:.4}\n", source, stats.lambda, stats.k, h),
+        None => format!("Statistics ({}):  lambda = {:.4}, K = {:.4}\n", source, stats.lambda, stats.k),
+    }
+}
+
+
+/// Renders the bit score and E-value of a local alignment between strings of lengths m and n
+pub fn render_significance(alignment: &Alignment, stats: &KarlinAltschul, m: usize, n: usize) -> String {
+    format!("Bit score = {:.1}, E-value = {:.2e}\n", stats.bit_score(alignment.score), stats.evalue(alignment.score, m, n))
+}
+
+
+/// Renders how an alignment's score compares to the scores of shuffled strings
+pub fn render_shuffle_test(alignment: &Alignment, test: &ShuffleTest) -> String {
+    format!("Shuffles = {} ({}), mean = {:.2}, sd = {:.2}, z-score = {:.2}, EVD p-value = {:.2e}, empirical p-value = {:.2e}\n",
+        test.scores.len(), test.kind.name(), test.mean, test.sd, test.z_score(alignment.score),
+        test.evd_pvalue(alignment.score), test.empirical_pvalue(alignment.score))
+}
+
+
+/// Renders what a codon alignment did with the codons
+pub fn render_codon_report(report: &CodonReport) -> String {
+    let mut out = String::new();
+    writeln!(out, "Codons:  aligned = {}, identical = {}, synonymous = {}, non-synonymous = {}",
+        report.codons, report.identical, report.synonymous, report.nonsynonymous).unwrap();
+    writeln!(out, "Codon gaps = {} ({} codons), frameshifts = {}", report.codon_gaps, report.gapped_codons, report.frameshifts).unwrap();
+    out
+}
+
+
+/// Renders a translated alignment as chunks of the DNA, its translation and the protein, followed by its report.
+/// DNA positions count down on the reverse strand.
+pub fn render_translated(alignment: &TranslatedAlignment, config: &Config) -> String {
+    let mut out = String::new();
+
+    // pad the names to the same width
+    let width = config.s1_name.len().max(config.s2_name.len()) + 2;
+    let dna_header = format!("{:<width$}", config.s1_name, width = width);
+    let protein_header = format!("{:<width$}", config.s2_name, width = width);
+    let blank_header = " ".repeat(width);
+    let max_num_len: usize = 2 + alignment.dna_end.max(alignment.protein_end).to_string().len();
+
+    let mut dna_counter = 0; //nucleotides shown so far
+    let mut protein_counter = alignment.protein_start;
+    let dna_position = |counter: usize| match alignment.strand { //1-based position of the next nucleotide
+        Strand::Forward => alignment.dna_start + counter + 1,
+        Strand::Reverse => alignment.dna_end - counter,
+    };
+
+    let mut chunk_start = 0;
+    while chunk_start < alignment.len() {
+        let chunk_end = (chunk_start + CHUNK_WIDTH).min(alignment.len());
+        let dna_chunk = &alignment.dna_row[chunk_start..chunk_end];
+        let protein_chunk = &alignment.protein_row[chunk_start..chunk_end];
+
+        let dna_counter_next = dna_counter + dna_chunk.len() - dna_chunk.matches('-').count();
+        let protein_counter_next = protein_counter + protein_chunk.chars().filter(|&c| c != ' ' && c != '-').count();
+        let dna_last = match alignment.strand { //position of the last nucleotide in this chunk
+            Strand::Forward => alignment.dna_start + dna_counter_next,
+            Strand::Reverse => alignment.dna_end + 1 - dna_counter_next,
+        };
+
+        let dna_label = dna_position(dna_counter).to_string();
+        let protein_label = (protein_counter + 1).to_string();
+        writeln!(out, "{}{}{}{}  {}", dna_header, dna_label, " ".repeat(max_num_len - dna_label.len()), dna_chunk, dna_last).unwrap();
+        writeln!(out, "{}{}{}", blank_header, " ".repeat(max_num_len), &alignment.translation_row[chunk_start..chunk_end]).unwrap();
+        writeln!(out, "{}{}{}", blank_header, " ".repeat(max_num_len), &alignment.ma_row[chunk_start..chunk_end]).unwrap();
+        writeln!(out, "{}{}{}{}  {}", protein_header, protein_label, " ".repeat(max_num_len - protein_label.len()), protein_chunk, protein_counter_next).unwrap();
+        if chunk_end - chunk_start == CHUNK_WIDTH { //only full chunks are followed by a blank line
+            writeln!(out).unwrap();
+        }
+
+        dna_counter = dna_counter_next;
+        protein_counter = protein_counter_next;
+        chunk_start = chunk_end;
+    }
+
+    writeln!(out, "\n\n").unwrap();
+    writeln!(out, "Report:\n").unwrap();
+    writeln!(out, "Translated optimal score = {}\n", alignment.score).unwrap();
+    writeln!(out, "Strand = Plus/{}, frame = {:+}\n", alignment.strand.name(), alignment.frame).unwrap();
+    writeln!(out, "DNA {}-{} (nt), protein {}-{} (aa)\n", alignment.dna_start + 1, alignment.dna_end,
+        alignment.protein_start + 1, alignment.protein_end).unwrap();
+    let percent = |count: usize| (100 * count).checked_div(alignment.codons).unwrap_or(0);
+    writeln!(out, "Identities = {}/{} ({}%), Positives = {}/{} ({}%)", alignment.identities, alignment.codons,
+        percent(alignment.identities), alignment.positives, alignment.codons, percent(alignment.positives)).unwrap();
+    writeln!(out, "Opening gaps = {}, codons against gaps = {}, residues against gaps = {}, frameshifts = {}",
+        alignment.gap_opens, alignment.gapped_codons, alignment.gapped_residues, alignment.frameshifts).unwrap();
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::AlignmentKind;
+    use crate::testing::config;
+
+    #[test]
+    fn render_alignment_numbers_rows_and_reports() {
+        let mut names = config("");
+        names.s1_name = String::from("query");
+        names.s2_name = String::from("s2");
+        let alignment = Alignment::from_rows(AlignmentKind::Local, String::from("AC--GTTA"), String::from("AGTA-TTA"), 2, 5, 7, &names);
+        let expected = "\
+query  3   AC--GTTA  8
+           |    |||
+s2     6   AGTA-TTA  12
+
+
+
+Report:
+
+Local optimal score = 7
+
+Number of:  matches = 4, mismatches = 1, opening gaps = 1, gap extensions = 3
+
+Identities = 4/8 (50%), Gaps = 3/8 (37%)
+
+Gap lengths:  1: 1, 2-5: 1, 6-20: 0, 21-100: 0, >100: 0
+";
+        assert_eq!(render_alignment(&alignment, &names), expected);
+
+        // a second chunk picks up the numbering where the first left off
+        let long = Alignment::from_rows(AlignmentKind::Global, "A".repeat(70), format!("{}{}", "-".repeat(5), "A".repeat(65)), 0, 0, 0, &names);
+        let rendered = render_alignment(&long, &names);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], format!("query  1   {}  60", "A".repeat(60)));
+        assert_eq!(lines[2], format!("s2     1   -----{}  55", "A".repeat(55)));
+        assert_eq!(lines[3], "");
+        assert_eq!(lines[4], format!("query  61  {}  70", "A".repeat(10)));
+        assert_eq!(lines[6], format!("s2     56  {}  65", "A".repeat(10)));
+    }
+}
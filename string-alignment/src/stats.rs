@@ -0,0 +1,292 @@
+use std::f64::consts::{LN_2, PI};
+use crate::random::{Rng, Shuffle};
+use crate::structs::Config;
+
+
+/// Euler-Mascheroni constant, the mean of a standard Gumbel distribution
+const EULER_GAMMA: f64 = 0.5772156649015329;
+
+/// Most terms of the series for K before giving up on it converging further
+const MAX_K_TERMS: usize = 200;
+
+
+/// Karlin-Altschul parameters of a scoring scheme, which turn raw local scores into bit scores and E-values
+#[derive(Debug, Clone, Copy)]
+pub struct KarlinAltschul {
+    pub lambda: f64,
+    pub k: f64,
+    pub h: Option<f64>, //relative entropy per aligned pair, only known for the analytic ungapped parameters
+    pub gapped: bool, //whether these were estimated by shuffling under the full gapped scheme, rather than ignoring gaps
+}
+impl KarlinAltschul {
+
+    /// Solves for the ungapped parameters exactly, with residue frequencies taken from the two strings.
+    /// Returns None when the scheme isn't a local one, i.e. random pairs don't score below 0 on average or nothing scores above 0.
+    pub fn ungapped(s1: &str, s2: &str, config: &Config) -> Option<Self> {
+        let (low, probabilities) = score_distribution(s1, s2, config)?;
+        let scores = || probabilities.iter().enumerate().map(|(k, &p)| (low + k as i32, p)).filter(|&(_, p)| p > 0.0);
+
+        let expected: f64 = scores().map(|(s, p)| s as f64 * p).sum();
+        if expected >= 0.0 || scores().all(|(s, _)| s <= 0) {
+            return None;
+        }
+
+        // lambda is the positive root of sum p(s) e^(lambda s) = 1
+        let moment = |lambda: f64| scores().map(|(s, p)| p * (lambda * s as f64).exp()).sum::<f64>();
+        let mut high = 0.5;
+        while moment(high) < 1.0 {
+            high *= 2.0;
+        }
+        let mut low_lambda = 0.0;
+        for _ in 0..200 {
+            let mid = (low_lambda + high) / 2.0;
+            if moment(mid) < 1.0 {
+                low_lambda = mid;
+            } else {
+                high = mid;
+            }
+        }
+        let lambda = (low_lambda + high) / 2.0;
+        let h = lambda * scores().map(|(s, p)| s as f64 * p * (lambda * s as f64).exp()).sum::<f64>();
+
+        // K = delta lambda e^(-2 sigma) / (H (1 - e^(-lambda delta))), where sigma sums over random walks of k steps
+        // (1/k) (E[e^(lambda S_k); S_k < 0] + P(S_k >= 0))
+        let delta = scores().fold(0, |d, (s, _)| gcd(d, s.unsigned_abs())) as f64;
+        let mut walk = probabilities.clone(); //distribution of S_k, starting at k = 1
+        let mut walk_low = low as i64;
+        let mut sigma = 0.0;
+        for k in 1..=MAX_K_TERMS {
+            let mut term = 0.0;
+            for (offset, &p) in walk.iter().enumerate() {
+                let s = walk_low + offset as i64;
+                term += if s < 0 {p * (lambda * s as f64).exp()} else {p};
+            }
+            sigma += term / k as f64;
+            if term / (k as f64) < 1e-12 {
+                break;
+            }
+
+            // add one more step to the walk
+            let mut next = vec![0.0; walk.len() + probabilities.len() - 1];
+            for (a, &pa) in walk.iter().enumerate() {
+                if pa == 0.0 {
+                    continue;
+                }
+                for (b, &pb) in probabilities.iter().enumerate() {
+                    next[a + b] += pa * pb;
+                }
+            }
+            walk = next;
+            walk_low += low as i64;
+        }
+        let k = delta * lambda * (-2.0 * sigma).exp() / (h * (1.0 - (-lambda * delta).exp()));
+
+        Some(KarlinAltschul { lambda, k, h: Some(h), gapped: false })
+    }
+
+    /// Estimates the gapped parameters by scoring `shuffles` shuffles of s2 against a string of length m with `score`,
+    /// the full local alignment, and fitting a Gumbel distribution to the scores by the method of moments.
+    /// Returns None if the scores don't spread out.
+    pub fn from_shuffles(m: usize, s2: &str, shuffles: usize, rng: &mut Rng, score: impl Fn(&str) -> i32) -> Option<Self> {
+        let scores = shuffled_scores(s2, shuffles, Shuffle::Mono, rng, score);
+        let (lambda, mu) = fit_gumbel(&scores)?;
+        let k = (lambda * mu).exp() / (m as f64 * s2.len() as f64);
+        Some(KarlinAltschul { lambda, k, h: None, gapped: true })
+    }
+
+    /// Score in bits, comparable across scoring schemes
+    pub fn bit_score(&self, score: i32) -> f64 {
+        (self.lambda * score as f64 - self.k.ln()) / LN_2
+    }
+
+    /// Expected number of local alignments scoring at least this much between random strings of lengths m and n
+    pub fn evalue(&self, score: i32, m: usize, n: usize) -> f64 {
+        self.k * m as f64 * n as f64 * (-self.lambda * score as f64).exp()
+    }
+}
+
+
+/// How a real score compares to the scores of s2's shuffles aligned the same way
+#[derive(Debug, Clone)]
+pub struct ShuffleTest {
+    pub kind: Shuffle,
+    pub scores: Vec<f64>,
+    pub mean: f64,
+    pub sd: f64,
+    pub lambda: f64, //rate of the extreme value distribution fitted to the scores
+    pub mu: f64, //and its location
+}
+impl ShuffleTest {
+
+    /// Scores `shuffles` shuffles of s2 with `score` and fits an extreme value distribution to them.
+    /// Returns None if the scores don't spread out.
+    pub fn run(s2: &str, shuffles: usize, kind: Shuffle, rng: &mut Rng, score: impl Fn(&str) -> i32) -> Option<Self> {
+        let scores = shuffled_scores(s2, shuffles, kind, rng, score);
+        let (lambda, mu) = fit_gumbel(&scores)?;
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        let sd = (scores.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (scores.len() - 1) as f64).sqrt();
+        Some(ShuffleTest { kind, scores, mean, sd, lambda, mu })
+    }
+
+    /// Standard deviations above the shuffled mean
+    pub fn z_score(&self, score: i32) -> f64 {
+        (score as f64 - self.mean) / self.sd
+    }
+
+    /// Chance a shuffle scores at least this much under the fitted extreme value distribution
+    pub fn evd_pvalue(&self, score: i32) -> f64 {
+        -(-(-self.lambda * (score as f64 - self.mu)).exp()).exp_m1()
+    }
+
+    /// Fraction of shuffles that scored at least this much, counting the real string as one of them
+    pub fn empirical_pvalue(&self, score: i32) -> f64 {
+        let at_least = self.scores.iter().filter(|&&x| x >= score as f64).count();
+        (at_least + 1) as f64 / (self.scores.len() + 1) as f64
+    }
+}
+
+
+/// Scores of `shuffles` shuffles of s2
+fn shuffled_scores(s2: &str, shuffles: usize, kind: Shuffle, rng: &mut Rng, score: impl Fn(&str) -> i32) -> Vec<f64> {
+    (0..shuffles)
+        .map(|_| {
+            let shuffled = rng.shuffle_string(s2.as_bytes(), kind);
+            score(&String::from_utf8_lossy(&shuffled)) as f64
+        })
+        .collect()
+}
+
+
+/// Fits a Gumbel distribution to the samples by the method of moments, returning its rate lambda and location mu
+pub fn fit_gumbel(samples: &[f64]) -> Option<(f64, f64)> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    if variance <= 0.0 {
+        return None;
+    }
+    let lambda = PI / (6.0 * variance).sqrt();
+    Some((lambda, mean - EULER_GAMMA / lambda))
+}
+
+
+/// Probability of every substitution score between a random character of s1 and one of s2,
+/// as the lowest score and the probabilities from there up
+fn score_distribution(s1: &str, s2: &str, config: &Config) -> Option<(i32, Vec<f64>)> {
+    if s1.is_empty() || s2.is_empty() {
+        return None;
+    }
+    let mut counts1 = [0usize; 256];
+    let mut counts2 = [0usize; 256];
+    s1.bytes().for_each(|c| counts1[c as usize] += 1);
+    s2.bytes().for_each(|c| counts2[c as usize] += 1);
+
+    let mut pairs: Vec<(i32, f64)> = Vec::new();
+    for c1 in (0..256).filter(|&c| counts1[c] > 0) {
+        for c2 in (0..256).filter(|&c| counts2[c] > 0) {
+            let p = counts1[c1] as f64 / s1.len() as f64 * counts2[c2] as f64 / s2.len() as f64;
+            pairs.push((config.substitution(c1 as u8, c2 as u8), p));
+        }
+    }
+    let low = pairs.iter().map(|&(s, _)| s).min()?;
+    let high = pairs.iter().map(|&(s, _)| s).max()?;
+    let mut probabilities = vec![0.0; (high - low + 1) as usize];
+    for (s, p) in pairs {
+        probabilities[(s - low) as usize] += p;
+    }
+    Some((low, probabilities))
+}
+
+
+/// Greatest common divisor
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {a} else {gcd(b, a % b)}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::striped::striped_smith_waterman;
+    use crate::testing::{config, random_string};
+
+    #[test]
+    fn ungapped_parameters_for_uniform_dna() {
+        let dna = "ACGT".repeat(25);
+        let stats = KarlinAltschul::ungapped(&dna, &dna, &config("match = 1\nmismatch = -3\n")).unwrap();
+        assert!((stats.lambda - 1.3741).abs() < 1e-4 && (stats.k - 0.7106).abs() < 1e-4, "{:?}", stats);
+        assert!(!stats.gapped);
+
+        let stats = KarlinAltschul::ungapped(&dna, &dna, &config("match = 1\nmismatch = -1\n")).unwrap();
+        assert!((stats.lambda - 3f64.ln()).abs() < 1e-9 && (stats.k - 1.0 / 3.0).abs() < 1e-6, "{:?}", stats);
+
+        // random pairs have to score below 0 on average
+        assert!(KarlinAltschul::ungapped(&dna, &dna, &config("match = 3\nmismatch = -1\n")).is_none());
+        assert!(KarlinAltschul::ungapped("", &dna, &config("")).is_none());
+    }
+
+    #[test]
+    fn bit_scores_and_evalues() {
+        let stats = KarlinAltschul { lambda: 3f64.ln(), k: 1.0 / 3.0, h: None, gapped: false };
+        assert!((stats.bit_score(5) - 6.0 * 3f64.log2()).abs() < 1e-9); //(5 ln 3 + ln 3) / ln 2
+        assert!((stats.evalue(5, 100, 200) - 20000.0 / 729.0).abs() < 1e-9); //100 * 200 / 3 * 3^-5
+        assert!((stats.evalue(0, 30, 10) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fit_gumbel_recovers_its_parameters() {
+        let (lambda, mu) = fit_gumbel(&[1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        assert!((lambda - PI / 15f64.sqrt()).abs() < 1e-12 && (mu - (3.0 - EULER_GAMMA / lambda)).abs() < 1e-12);
+        assert!(fit_gumbel(&[2.0]).is_none() && fit_gumbel(&[2.0, 2.0, 2.0]).is_none());
+
+        // draws by inverting the Gumbel distribution function
+        let mut rng = Rng::new(42);
+        let samples: Vec<f64> = (0..20000).map(|_| {
+            let u = (rng.below(1 << 30) as f64 + 0.5) / (1u64 << 30) as f64;
+            10.0 - (-u.ln()).ln() / 0.3
+        }).collect();
+        let (lambda, mu) = fit_gumbel(&samples).unwrap();
+        assert!((lambda - 0.3).abs() < 0.01 && (mu - 10.0).abs() < 0.1, "{} {}", lambda, mu);
+    }
+
+    #[test]
+    fn gapped_parameters_from_shuffles() {
+        let config = config("match = 1\nmismatch = -3\nh = -5\ng = -2\n");
+        let mut rng = Rng::new(43);
+        let s1 = random_string(&mut rng, b"ACGT", 200);
+        let s2 = random_string(&mut rng, b"ACGT", 200);
+        let stats = KarlinAltschul::from_shuffles(s1.len(), &s2, 200, &mut rng, |shuffled| striped_smith_waterman(&s1, shuffled, &config)).unwrap();
+        assert!(stats.gapped && stats.h.is_none());
+
+        // gaps only add ways to score, so the gapped scores decay no faster than the ungapped ones
+        let ungapped = KarlinAltschul::ungapped(&s1, &s2, &config).unwrap();
+        assert!(stats.lambda > 0.5 && stats.lambda < ungapped.lambda * 1.2, "{:?} {:?}", stats, ungapped);
+        assert!(KarlinAltschul::from_shuffles(s1.len(), &s2, 10, &mut rng, |_| 7).is_none());
+    }
+
+    #[test]
+    fn shuffle_test_pvalues_on_a_fixed_sample() {
+        let (lambda, mu) = fit_gumbel(&[1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        let test = ShuffleTest { kind: Shuffle::Mono, scores: vec![1.0, 2.0, 3.0, 4.0, 5.0], mean: 3.0, sd: 2.5f64.sqrt(), lambda, mu };
+        assert!((test.evd_pvalue(4) - 0.220795).abs() < 1e-6);
+        assert!((test.evd_pvalue(1) - 0.941788).abs() < 1e-6);
+        assert_eq!((test.empirical_pvalue(4), test.empirical_pvalue(6), test.empirical_pvalue(0)), (0.5, 1.0 / 6.0, 1.0));
+        assert!((test.z_score(5) - 2.0 / 2.5f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn shuffle_test_scores_every_shuffle() {
+        let mut rng = Rng::new(41);
+        let s2 = "AACCGGTTAACCGGTTAACCGGTT";
+        let test = ShuffleTest::run(s2, 30, Shuffle::Di, &mut rng, |shuffled| shuffled.matches("ACG").count() as i32).unwrap();
+        assert_eq!(test.scores.len(), 30);
+        let mean = test.scores.iter().sum::<f64>() / 30.0;
+        assert!((test.mean - mean).abs() < 1e-12);
+        assert_eq!((test.lambda, test.mu), fit_gumbel(&test.scores).unwrap());
+
+        // every shuffle keeps the count of A, so there's nothing to fit
+        assert!(ShuffleTest::run(s2, 30, Shuffle::Di, &mut rng, |shuffled| shuffled.matches('A').count() as i32).is_none());
+    }
+}
@@ -0,0 +1,362 @@
+use crate::algorithms::optimal_score;
+use crate::structs::Config;
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+
+/// Which instructions a striped alignment runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Avx2, //16 lanes of 16 bits
+    Sse2, //8 lanes of 16 bits
+    Scalar, //the two-row score-only fill
+}
+impl Backend {
+
+    /// The widest backend this CPU supports
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Backend::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Backend::Sse2;
+            }
+        }
+        Backend::Scalar
+    }
+
+    /// Whether this CPU can run the backend
+    pub fn available(&self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => is_x86_feature_detected!("sse2"),
+            Backend::Scalar => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Name to show in the output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Avx2 => "AVX2",
+            Backend::Sse2 => "SSE2",
+            Backend::Scalar => "scalar",
+        }
+    }
+}
+
+
+/// Optimal local alignment score by Farrar's striped Smith-Waterman on the best available backend
+pub fn striped_smith_waterman(s1: &str, s2: &str, config: &Config) -> i32 {
+    striped_smith_waterman_with(Backend::detect(), s1, s2, config)
+}
+
+
+/// Optimal local alignment score by Farrar's striped Smith-Waterman on the given backend.
+/// Scores are kept in 16 bits, so whenever they could have saturated the scalar fill is run instead, and
+/// the result is always the same as `smith_waterman`'s. Masked seeds and gap tracks aren't striped, so they always take the scalar fill.
+pub fn striped_smith_waterman_with(backend: Backend, s1: &str, s2: &str, config: &Config) -> i32 {
+    if backend == Backend::Scalar || !backend.available() || config.mask_seeds || config.gap_track.is_some() || !fits_in_lanes(s1, s2, config) {
+        return optimal_score(s1, s2, config, true);
+    }
+    let score = match backend {
+        #[cfg(target_arch = "x86_64")]
+        Backend::Avx2 => unsafe { striped_avx2(s1.as_bytes(), s2.as_bytes(), config) },
+        #[cfg(target_arch = "x86_64")]
+        Backend::Sse2 => unsafe { striped_sse2(s1.as_bytes(), s2.as_bytes(), config) },
+        _ => None,
+    };
+    score.unwrap_or_else(|| optimal_score(s1, s2, config, true))
+}
+
+
+/// Whether every substitution and gap score fits in 16 bits, with gaps as penalties
+fn fits_in_lanes(s1: &str, s2: &str, config: &Config) -> bool {
+    let limit = i16::MAX as i32 / 4;
+    let gaps_fit = [(config.h1, config.g1), (config.h2, config.g2)].iter().all(|&(h, g)| h <= 0 && g <= 0 && h + g > -limit);
+    let mut in_s1 = [false; 256];
+    let mut in_s2 = [false; 256];
+    s1.bytes().for_each(|c| in_s1[c as usize] = true);
+    s2.bytes().for_each(|c| in_s2[c as usize] = true);
+    gaps_fit && (0..256).filter(|&c1| in_s1[c1]).all(|c1| {
+        (0..256).filter(|&c2| in_s2[c2]).all(|c2| config.substitution(c1 as u8, c2 as u8).abs() < limit)
+    })
+}
+
+
+/// The operations the striped fill needs from a vector of 16-bit lanes
+#[cfg(target_arch = "x86_64")]
+trait Lanes: Copy {
+    const LANES: usize;
+    unsafe fn splat(x: i16) -> Self;
+    unsafe fn from_slice(values: &[i16]) -> Self;
+    unsafe fn adds(self, other: Self) -> Self; //saturating
+    unsafe fn subs(self, other: Self) -> Self; //saturating
+    unsafe fn max(self, other: Self) -> Self;
+    unsafe fn shift_in_zero(self) -> Self; //moves every lane up by one, with 0 in lane 0
+    unsafe fn any_greater(self, other: Self) -> bool;
+    unsafe fn horizontal_max(self) -> i16;
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m128i {
+    const LANES: usize = 8;
+    #[inline(always)]
+    unsafe fn splat(x: i16) -> Self { _mm_set1_epi16(x) }
+    #[inline(always)]
+    unsafe fn from_slice(values: &[i16]) -> Self { _mm_loadu_si128(values.as_ptr() as *const __m128i) }
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { _mm_adds_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { _mm_subs_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { _mm_max_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn shift_in_zero(self) -> Self { _mm_slli_si128::<2>(self) }
+    #[inline(always)]
+    unsafe fn any_greater(self, other: Self) -> bool { _mm_movemask_epi8(_mm_cmpgt_epi16(self, other)) != 0 }
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i16 {
+        let mut values = [0i16; 8];
+        _mm_storeu_si128(values.as_mut_ptr() as *mut __m128i, self);
+        values.into_iter().max().unwrap()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for __m256i {
+    const LANES: usize = 16;
+    #[inline(always)]
+    unsafe fn splat(x: i16) -> Self { _mm256_set1_epi16(x) }
+    #[inline(always)]
+    unsafe fn from_slice(values: &[i16]) -> Self { _mm256_loadu_si256(values.as_ptr() as *const __m256i) }
+    #[inline(always)]
+    unsafe fn adds(self, other: Self) -> Self { _mm256_adds_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn subs(self, other: Self) -> Self { _mm256_subs_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn max(self, other: Self) -> Self { _mm256_max_epi16(self, other) }
+    #[inline(always)]
+    unsafe fn shift_in_zero(self) -> Self { //the byte shift works within each 128-bit half, so carry the low half's top lane over by hand
+        let low_in_high = _mm256_permute2x128_si256::<0x08>(self, self);
+        _mm256_alignr_epi8::<14>(self, low_in_high)
+    }
+    #[inline(always)]
+    unsafe fn any_greater(self, other: Self) -> bool { _mm256_movemask_epi8(_mm256_cmpgt_epi16(self, other)) != 0 }
+    #[inline(always)]
+    unsafe fn horizontal_max(self) -> i16 {
+        let mut values = [0i16; 16];
+        _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, self);
+        values.into_iter().max().unwrap()
+    }
+}
+
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn striped_sse2(s1: &[u8], s2: &[u8], config: &Config) -> Option<i32> {
+    striped::<__m128i>(s1, s2, config)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn striped_avx2(s1: &[u8], s2: &[u8], config: &Config) -> Option<i32> {
+    striped::<__m256i>(s1, s2, config)
+}
+
+
+/// Farrar's striped fill with s1 as the query down the lanes and s2 streamed past it.
+/// Query position k lives in lane k / segments of vector k % segments, so a vertical gap only crosses lanes once per column,
+/// and the lazy F loop fixes up the few columns where one does. Returns None if the score got close enough to saturate.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn striped<V: Lanes>(s1: &[u8], s2: &[u8], config: &Config) -> Option<i32> {
+    if s1.is_empty() || s2.is_empty() {
+        return Some(0);
+    }
+    let segments = s1.len().div_ceil(V::LANES);
+    let padding = i16::MIN / 2; //past the end of s1, low enough that nothing is ever built on it
+
+    // one striped row of substitution scores per distinct character of s2
+    let mut slot = [usize::MAX; 256];
+    let mut profile: Vec<V> = Vec::new();
+    let mut lanes = vec![0i16; V::LANES];
+    for &c2 in s2 {
+        if slot[c2 as usize] != usize::MAX {
+            continue;
+        }
+        slot[c2 as usize] = profile.len() / segments;
+        for segment in 0..segments {
+            for (lane, value) in lanes.iter_mut().enumerate() {
+                let k = lane * segments + segment;
+                *value = if k < s1.len() {config.substitution(s1[k], c2) as i16} else {padding};
+            }
+            profile.push(V::from_slice(&lanes));
+        }
+    }
+
+    let zero = V::splat(0);
+    let e_open = V::splat((-(config.h1 + config.g1)) as i16); //first character of a gap in s1
+    let e_extend = V::splat((-config.g1) as i16);
+    let f_open = V::splat((-(config.h2 + config.g2)) as i16); //first character of a gap in s2
+    let f_extend = V::splat((-config.g2) as i16);
+    let mut h_store: Vec<V> = vec![zero; segments];
+    let mut h_load: Vec<V> = vec![zero; segments];
+    let mut e_store: Vec<V> = vec![zero; segments]; //best score ending in a gap in s1, one column on
+    let mut best = zero;
+
+    for &c2 in s2 {
+        let scores = &profile[slot[c2 as usize] * segments..(slot[c2 as usize] + 1) * segments];
+        let mut f = zero; //best score ending in a gap in s2
+        let mut h = h_store[segments-1].shift_in_zero(); //diagonal neighbour of segment 0
+        std::mem::swap(&mut h_load, &mut h_store);
+
+        for segment in 0..segments {
+            h = h.adds(scores[segment]);
+            let e = e_store[segment];
+            h = h.max(e).max(f).max(zero);
+            best = best.max(h);
+            h_store[segment] = h;
+
+            e_store[segment] = e.subs(e_extend).max(h.subs(e_open)).max(zero);
+            f = f.subs(f_extend).max(h.subs(f_open)).max(zero);
+            h = h_load[segment];
+        }
+
+        // carry vertical gaps across lanes until they can no longer improve anything
+        'lazy_f: for _ in 0..V::LANES {
+            f = f.shift_in_zero();
+            for segment in 0..segments {
+                if !f.any_greater(h_store[segment].subs(f_open)) { //the main loop already carried anything this good
+                    break 'lazy_f;
+                }
+                let h = h_store[segment].max(f);
+                h_store[segment] = h;
+                best = best.max(h);
+                e_store[segment] = e_store[segment].max(h.subs(e_open));
+                f = f.subs(f_extend);
+            }
+        }
+    }
+
+    let best = best.horizontal_max() as i32;
+    let max_substitution = config.max_substitution();
+    if best + max_substitution >= i16::MAX as i32 {
+        return None;
+    }
+    Some(best)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::smith_waterman;
+    use crate::random::Rng;
+    use crate::testing::random_up_to;
+
+    /// Copies s with about one character in ten dropped, one replaced and one followed by an extra character
+    fn mutate(rng: &mut Rng, s: &str, alphabet: &[u8]) -> String {
+        let mut out = String::new();
+        for c in s.chars() {
+            match rng.below(10) {
+                0 => {}, //drop it
+                1 => out.push(alphabet[rng.below(alphabet.len())] as char),
+                2 => {
+                    out.push(c);
+                    out.push(alphabet[rng.below(alphabet.len())] as char);
+                },
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+
+    fn config(matrix: Option<&str>, true_match: i32, mismatch: i32, h: i32, g: i32) -> Config {
+        let matrix = matrix.map_or(String::new(), |name| format!("matrix = {}\n", name));
+        crate::testing::config(&format!("{}match = {}\nmismatch = {}\nh = {}\ng = {}\n", matrix, true_match, mismatch, h, g))
+    }
+
+    const BACKENDS: [Backend; 3] = [Backend::Avx2, Backend::Sse2, Backend::Scalar];
+
+    fn check(s1: &str, s2: &str, config: &Config) {
+        let expected = smith_waterman(s1, s2, config).score;
+        for backend in BACKENDS.iter().filter(|backend| backend.available()) {
+            assert_eq!(striped_smith_waterman_with(*backend, s1, s2, config), expected,
+                "{} differs on {:?} vs {:?} with h1 = {}, g1 = {}, h2 = {}, g2 = {}", backend.name(), s1, s2,
+                config.h1, config.g1, config.h2, config.g2);
+        }
+    }
+
+    #[test]
+    fn random_dna_matches_scalar() {
+        let mut rng = Rng::new(0x2545f4914f6cdd1d);
+        let alphabet = b"ACGT";
+        for round in 0..400 {
+            let config = config(None, 1 + rng.below(3) as i32, -(1 + rng.below(4) as i32), -(rng.below(8) as i32), -(rng.below(4) as i32));
+            let s1 = random_up_to(&mut rng, alphabet, 80);
+            let s2 = if round % 2 == 0 {mutate(&mut rng, &s1, alphabet)} else {random_up_to(&mut rng, alphabet, 80)};
+            check(&s1, &s2, &config);
+        }
+    }
+
+    #[test]
+    fn random_protein_matches_scalar() {
+        let mut rng = Rng::new(0x9e3779b97f4a7c15);
+        let alphabet = b"ARNDCQEGHILKMFPSTWYV";
+        for (round, matrix) in ["BLOSUM62", "PAM30", "BLOSUM45", "PAM250"].iter().cycle().take(200).enumerate() {
+            let config = config(Some(matrix), 0, 0, -(rng.below(12) as i32), -(rng.below(3) as i32));
+            let s1 = random_up_to(&mut rng, alphabet, 150);
+            let s2 = if round % 2 == 0 {mutate(&mut rng, &s1, alphabet)} else {random_up_to(&mut rng, alphabet, 150)};
+            check(&s1, &s2, &config);
+        }
+    }
+
+    #[test]
+    fn asymmetric_gaps_match_scalar() {
+        let mut rng = Rng::new(0x853c49e6748fea9b);
+        let alphabet = b"ACGT";
+        for round in 0..200 {
+            let mut config = config(None, 2, -3, -(rng.below(8) as i32), -(rng.below(4) as i32));
+            config.h1 = -(rng.below(8) as i32);
+            config.g1 = -(rng.below(4) as i32);
+            let s1 = random_up_to(&mut rng, alphabet, 100);
+            let s2 = if round % 2 == 0 {mutate(&mut rng, &s1, alphabet)} else {random_up_to(&mut rng, alphabet, 100)};
+            check(&s1, &s2, &config);
+        }
+    }
+
+    #[test]
+    fn long_gaps_cross_lanes() {
+        // a long insertion forces vertical gaps through every lane and segment
+        let config = config(None, 2, -3, -4, -1);
+        let s1 = "ACGTTGCAAGGCTTAACCGGTTACGATCGATCGGATCCATGCAAGCTTGACGTAGCTAGCTAGGCTA".repeat(3);
+        let s2 = format!("{}{}", &s1[..60], &s1[120..]);
+        check(&s1, &s2, &config);
+        check(&s2, &s1, &config);
+    }
+
+    #[test]
+    fn edge_cases() {
+        let config = config(None, 1, -2, -5, -1);
+        check("", "ACGT", &config);
+        check("ACGT", "", &config);
+        check("A", "A", &config);
+        check("A", "C", &config);
+        check("ACGTACG", "TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTACGTACG", &config);
+    }
+
+    #[test]
+    fn saturation_falls_back_to_scalar() {
+        // identical strings score past what 16 bits can hold
+        let config = config(None, 8, -2, -5, -1);
+        let s = "ACGT".repeat(1100);
+        check(&s, &s, &config);
+    }
+}
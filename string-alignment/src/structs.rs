@@ -1,3 +1,11 @@
+use crate::dna::is_ambiguous;
+use crate::gaps::GapModel;
+use crate::matrices::SubstitutionMatrix;
+
+
+/// Score of a state no path can be in, low enough to never be chosen and high enough to never overflow
+pub const REAL_MIN: i32 = i32::MIN / 2;
+
 /// Multi-dimensional contiguous matrix
 #[derive(Debug)]
 pub struct Matrix<T: Default + Clone> {
@@ -27,7 +35,7 @@ impl<T: Default + Clone> Matrix<T> {
 
 
 /// Makes up one cell of a table
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cell {
     pub d_score: i32,
     pub i_score: i32,   
@@ -44,19 +52,52 @@ impl Cell {
 
     /// Super-optimized comparison maxxing algorithm
     pub fn score(&self) -> i32 {
-        if self.d_score > self.i_score {
-            if self.d_score > self.s_score {
-                self.d_score
-            } else {
-                self.s_score
-            }
+        max3(self.d_score, self.i_score, self.s_score)
+    }
+
+    /// Computes the cell in row i from its upper, left and diagonal neighbours under the affine gap model
+    #[inline]
+    pub fn next(up: &Cell, left: &Cell, diag: &Cell, match_score: i32, config: &Config, i: usize) -> Cell {
+        let d_open = config.d_open(i);
+        let i_open = config.i_open(i);
+        Cell {
+            d_score: max3(up.d_score + config.g2, up.i_score + d_open + config.g2, up.s_score + d_open + config.g2),
+            i_score: max3(left.d_score + i_open + config.g1, left.i_score + config.g1, left.s_score + i_open + config.g1),
+            s_score: match_score + diag.score(),
+        }
+    }
+
+    /// Keeps a local cell from starting a new alignment on a pair of characters the config won't seed from
+    #[inline]
+    pub fn mask_seed(&mut self, diag: &Cell, c1: u8, c2: u8, config: &Config) {
+        if config.mask_seeds && diag.score() == 0 && (c1.is_ascii_lowercase() || c2.is_ascii_lowercase()) {
+            self.s_score = 0;
+        }
+    }
+
+    /// Raises any negative scores to 0, as local alignment requires
+    #[inline]
+    pub fn clamp(&mut self) {
+        self.d_score = self.d_score.max(0);
+        self.i_score = self.i_score.max(0);
+        self.s_score = self.s_score.max(0);
+    }
+}
+
+
+/// Returns the largest of three scores
+#[inline]
+fn max3(a: i32, b: i32, c: i32) -> i32 {
+    if a > b {
+        if a > c {
+            a
         } else {
-            if self.i_score > self.s_score {
-                self.i_score
-            } else {
-                self.s_score
-            }
+            c
         }
+    } else if b > c {
+        b
+    } else {
+        c
     }
 }
 impl Default for Cell {
@@ -67,13 +108,465 @@ impl Default for Cell {
 
 
 /// Used to keep config settings in one place
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub true_match: i32,
     pub mismatch: i32,
-    pub h: i32,
-    pub g: i32,
+    pub matrix: Option<SubstitutionMatrix>, //replaces true_match and mismatch when given
+    pub h1: i32, //gap open penalty in s1, where s2's characters line up with nothing
+    pub g1: i32, //and its extend penalty
+    pub h2: i32, //gap open penalty in s2, where s1's characters line up with nothing
+    pub g2: i32,
+    pub gap_track: Option<Vec<f64>>, //multiplies the open penalty of gaps at each position of s1
+    pub gap_model: GapModel, //how gap scores grow with length, affine unless chosen otherwise
+    pub ignore_case: bool, //score lowercase characters like uppercase ones
+    pub mask_seeds: bool, //local alignments can't start on a lowercase (soft-masked) character, though they can run through one
     pub s1_name: String,
     pub s2_name: String,
 }
+impl Config {
+
+    /// Score of aligning c1 with c2
+    #[inline]
+    pub fn substitution(&self, c1: u8, c2: u8) -> i32 {
+        match &self.matrix {
+            Some(matrix) => matrix.score(c1, c2),
+            None => if self.same_residue(c1, c2) {
+                self.true_match
+            } else {
+                self.mismatch
+            },
+        }
+    }
+
+    /// Whether two characters count as a match: equal, or equal but for case when case is ignored.
+    /// Matrices score lowercase like uppercase, so case never matters with one.
+    pub fn same_residue(&self, c1: u8, c2: u8) -> bool {
+        c1 == c2 || ((self.ignore_case || self.matrix.is_some()) && c1.eq_ignore_ascii_case(&c2))
+    }
+
+    /// Open penalty of a gap in s2 whose first character lines up with s1[i-1], i.e. one entering row i
+    #[inline]
+    pub fn d_open(&self, i: usize) -> i32 {
+        self.scaled_open(self.h2, i)
+    }
+
+    /// Open penalty of a gap in s1 right after s1[i-1], or before s1[0] when i is 0
+    #[inline]
+    pub fn i_open(&self, i: usize) -> i32 {
+        self.scaled_open(self.h1, i)
+    }
+
+    /// Scales an open penalty by the gap track at row i
+    #[inline]
+    fn scaled_open(&self, h: i32, i: usize) -> i32 {
+        match &self.gap_track {
+            None => h,
+            Some(track) => (h as f64 * track[i.max(1) - 1]).round() as i32,
+        }
+    }
+
+    /// Score of a gap of k characters in s1 under the gap model, ignoring the gap track
+    #[inline]
+    pub fn s1_gap(&self, k: usize) -> i32 {
+        self.gap_model.score(self.h1, self.g1, k, false)
+    }
+
+    /// Score of a gap of k characters in s2
+    #[inline]
+    pub fn s2_gap(&self, k: usize) -> i32 {
+        self.gap_model.score(self.h2, self.g2, k, true)
+    }
+
+    /// Least costly way to open a gap anywhere, in either string
+    pub fn cheapest_open(&self) -> i32 {
+        match &self.gap_track {
+            None => self.h1.max(self.h2),
+            Some(track) => (0..track.len()+1).map(|i| self.d_open(i).max(self.i_open(i))).max().unwrap_or(self.h1.max(self.h2)),
+        }
+    }
+
+    /// The same scoring with the roles of s1 and s2 swapped, which can't carry a gap track along s1
+    pub fn swapped(&self) -> Config {
+        Config {
+            h1: self.h2,
+            g1: self.g2,
+            h2: self.h1,
+            g2: self.g1,
+            gap_track: None,
+            gap_model: match self.gap_model {
+                GapModel::TwoPiece { h1, g1, h2, g2 } => GapModel::TwoPiece { h1: h2, g1: g2, h2: h1, g2: g1 },
+                model => model,
+            },
+            s1_name: self.s2_name.clone(),
+            s2_name: self.s1_name.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Highest score any pair of characters can get
+    pub fn max_substitution(&self) -> i32 {
+        match &self.matrix {
+            Some(matrix) => matrix.max_score(),
+            None => self.true_match.max(self.mismatch),
+        }
+    }
+}
+
+
+
+/// Which kind of alignment produced an `Alignment`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentKind {
+    Global,
+    Local,
+    SemiGlobal,
+    Overlap,
+    Glocal,
+    Codon, //global, a codon at a time
+}
+impl AlignmentKind {
+    /// Name used in the report
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlignmentKind::Global => "Global",
+            AlignmentKind::Local => "Local",
+            AlignmentKind::SemiGlobal => "Semi-global",
+            AlignmentKind::Overlap => "Overlap",
+            AlignmentKind::Glocal => "Glocal",
+            AlignmentKind::Codon => "Codon",
+        }
+    }
+}
+
+
+/// Which strand of s2 an `Alignment` is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse, //s2 was reverse complemented before aligning
+}
+impl Strand {
+    /// Name used in the report
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strand::Forward => "Plus",
+            Strand::Reverse => "Minus",
+        }
+    }
+}
 
+
+/// Which ends of each string may go unaligned without paying for the gap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndGaps {
+    pub s1_start: bool,
+    pub s1_end: bool,
+    pub s2_start: bool,
+    pub s2_end: bool,
+}
+impl EndGaps {
+    /// Every end is free, so either string may hang off either end of the other
+    pub fn semi_global() -> Self {
+        EndGaps { s1_start: true, s1_end: true, s2_start: true, s2_end: true }
+    }
+
+    /// A suffix of s1 overlaps a prefix of s2, as when assembling reads
+    pub fn overlap() -> Self {
+        EndGaps { s1_start: true, s1_end: false, s2_start: false, s2_end: true }
+    }
+
+    /// All of s2 aligns somewhere inside s1, as when finding a gene in a genome
+    pub fn glocal() -> Self {
+        EndGaps { s1_start: true, s1_end: true, s2_start: false, s2_end: false }
+    }
+
+    /// Parses a comma separated list of free ends, e.g. "s1_start,s2_end"
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut ends = EndGaps { s1_start: false, s1_end: false, s2_start: false, s2_end: false };
+        for end in list.split(',').map(str::trim).filter(|end| !end.is_empty()) {
+            match end {
+                "s1_start" => ends.s1_start = true,
+                "s1_end" => ends.s1_end = true,
+                "s2_start" => ends.s2_start = true,
+                "s2_end" => ends.s2_end = true,
+                _ => return Err(format!("unknown end \"{}\", expected s1_start, s1_end, s2_start or s2_end", end)),
+            }
+        }
+        Ok(ends)
+    }
+}
+
+
+/// An alignment between two strings, along with its statistics
+#[derive(Debug, Clone)]
+pub struct Alignment {
+    pub kind: AlignmentKind,
+    pub s1_row: String, //s1 with gaps inserted
+    pub ma_row: String, //'|' wherever the two rows match
+    pub s2_row: String, //s2 with gaps inserted
+    pub s1_start: usize, //0-based, inclusive
+    pub s1_end: usize, //0-based, exclusive
+    pub s2_start: usize, //always counted along the original s2, whichever strand was aligned
+    pub s2_end: usize,
+    pub strand: Strand,
+    pub score: i32,
+    pub matches: usize,
+    pub mismatches: usize,
+    pub ambiguous: usize, //pairs with a nucleotide ambiguity code, once they're set aside from the matches and mismatches
+    pub gap_opens: usize,
+    pub gap_extensions: usize,
+}
+impl Alignment {
+
+    /// Builds an alignment from its two gapped rows, counting matches, mismatches and gaps.
+    /// Matches follow the config's case rule; a gap opens wherever one starts after a non-gap column.
+    pub fn from_rows(kind: AlignmentKind, s1_row: String, s2_row: String, s1_start: usize, s2_start: usize, score: i32, config: &Config) -> Self {
+        let mut ma_row = String::with_capacity(s1_row.len());
+        let mut matches = 0;
+        let mut mismatches = 0;
+        let mut gap_opens = 0;
+        let mut gap_extensions = 0;
+        let mut s1_gaps = 0;
+        let mut s2_gaps = 0;
+        let mut in_gap = false;
+
+        for (&c1, &c2) in s1_row.as_bytes().iter().zip(s2_row.as_bytes()) {
+            if c1 == b'-' || c2 == b'-' { //encountered a gap
+                if c1 == b'-' {
+                    s1_gaps += 1;
+                } else {
+                    s2_gaps += 1;
+                }
+                if !in_gap {
+                    in_gap = true;
+                    gap_opens += 1;
+                }
+                gap_extensions += 1;
+                ma_row.push(' ');
+            } else if config.same_residue(c1, c2) { //found a match
+                matches += 1;
+                in_gap = false;
+                ma_row.push('|');
+            } else { //not a match or a gap, ie a mismatch
+                mismatches += 1;
+                in_gap = false;
+                ma_row.push(' ');
+            }
+        }
+
+        let s1_end = s1_start + s1_row.len() - s1_gaps;
+        let s2_end = s2_start + s2_row.len() - s2_gaps;
+        Alignment {
+            kind,
+            s1_row,
+            ma_row,
+            s2_row,
+            s1_start,
+            s1_end,
+            s2_start,
+            s2_end,
+            strand: Strand::Forward,
+            score,
+            matches,
+            mismatches,
+            ambiguous: 0,
+            gap_opens,
+            gap_extensions,
+        }
+    }
+
+    /// Marks an alignment against the reverse complement of s2 as such, moving its s2 coordinates
+    /// onto the original s2. The rows stay as aligned, so s2_row reads along the reverse complement.
+    pub fn on_reverse_strand(mut self, s2_len: usize) -> Self {
+        let (start, end) = (s2_len - self.s2_end, s2_len - self.s2_start);
+        self.s2_start = start;
+        self.s2_end = end;
+        self.strand = Strand::Reverse;
+        self
+    }
+
+    /// Counts pairs where either character is a nucleotide ambiguity code (N included) as ambiguous
+    /// rather than as matches or mismatches, since neither is known for sure
+    pub fn set_aside_ambiguous(&mut self) {
+        for ((&c1, &c2), &ma) in self.s1_row.as_bytes().iter().zip(self.s2_row.as_bytes()).zip(self.ma_row.as_bytes()) {
+            if c1 == b'-' || c2 == b'-' || !(is_ambiguous(c1) || is_ambiguous(c2)) {
+                continue;
+            }
+            if ma == b'|' { //counted as a match by from_rows
+                self.matches -= 1;
+            } else {
+                self.mismatches -= 1;
+            }
+            self.ambiguous += 1;
+        }
+    }
+
+    /// Number of columns in the alignment
+    pub fn len(&self) -> usize {
+        self.s1_row.len()
+    }
+
+    /// Whether the alignment has no columns
+    pub fn is_empty(&self) -> bool {
+        self.s1_row.is_empty()
+    }
+
+    /// Percentage of columns that are matches
+    pub fn identity(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.matches as f64 / self.len() as f64 * 100.0
+    }
+
+    /// Length of every gap, a run of columns with a gap in the same string, in order
+    pub fn gap_lengths(&self) -> Vec<usize> {
+        let mut lengths: Vec<usize> = Vec::new();
+        let mut last_gap: u8 = 0; //0: not in a gap, 1: gap in s1, 2: gap in s2
+        for (&c1, &c2) in self.s1_row.as_bytes().iter().zip(self.s2_row.as_bytes()) {
+            let this_gap = if c1 == b'-' {
+                1
+            } else if c2 == b'-' {
+                2
+            } else {
+                0
+            };
+            if this_gap != 0 && this_gap == last_gap {
+                *lengths.last_mut().unwrap() += 1;
+            } else if this_gap != 0 {
+                lengths.push(1);
+            }
+            last_gap = this_gap;
+        }
+        lengths
+    }
+
+    /// Percentage of columns that are gaps
+    pub fn gap_percent(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.gap_extensions as f64 / self.len() as f64 * 100.0
+    }
+}
+
+
+/// Distances between every pair of named strings
+#[derive(Debug)]
+pub struct DistanceMatrix {
+    pub names: Vec<String>,
+    pub distances: Matrix<f64>,
+}
+impl DistanceMatrix {
+
+    /// Creates an all-zero distance matrix for these names
+    pub fn new(names: Vec<String>) -> Self {
+        let distances = Matrix::with_shape(names.len(), names.len());
+        DistanceMatrix { names, distances }
+    }
+
+    /// Number of strings in the matrix
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether the matrix has no strings
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Distance between strings i and j
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        *self.distances.index(i, j)
+    }
+
+    /// Sets the distance between strings i and j, in both directions
+    pub fn set(&mut self, i: usize, j: usize, distance: f64) {
+        *self.distances.index_mut(i, j) = distance;
+        *self.distances.index_mut(j, i) = distance;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::smith_waterman;
+    use crate::testing::config;
+
+    #[test]
+    fn from_rows_counts_columns() {
+        let alignment = Alignment::from_rows(AlignmentKind::Local, String::from("AC--GTTA"), String::from("AGTA-TTA"), 2, 5, 7, &config(""));
+        assert_eq!(alignment.ma_row, "|    |||");
+        assert_eq!((alignment.matches, alignment.mismatches, alignment.gap_opens, alignment.gap_extensions), (4, 1, 1, 3));
+        assert_eq!((alignment.s1_start, alignment.s1_end, alignment.s2_start, alignment.s2_end), (2, 8, 5, 12));
+        assert_eq!((alignment.score, alignment.gap_lengths()), (7, vec![2, 1]));
+
+        let empty = Alignment::from_rows(AlignmentKind::Global, String::new(), String::new(), 0, 0, 0, &config(""));
+        assert_eq!((empty.matches, empty.gap_opens, empty.s1_end, empty.s2_end), (0, 0, 0, 0));
+    }
+    #[test]
+    fn from_rows_follows_the_case_rule() {
+        let strict = Alignment::from_rows(AlignmentKind::Global, String::from("AcGt"), String::from("ACGT"), 0, 0, 0, &config(""));
+        assert_eq!((strict.ma_row.as_str(), strict.matches, strict.mismatches), ("| | ", 2, 2));
+
+        let ignored = Alignment::from_rows(AlignmentKind::Global, String::from("AcGt"), String::from("ACGT"), 0, 0, 0, &config("ignore-case = true\n"));
+        assert_eq!((ignored.ma_row.as_str(), ignored.matches, ignored.mismatches), ("||||", 4, 0));
+
+        let matrix = Alignment::from_rows(AlignmentKind::Global, String::from("mKw"), String::from("MKV"), 0, 0, 0, &config("matrix = BLOSUM62\n"));
+        assert_eq!((matrix.matches, matrix.mismatches), (2, 1));
+    }
+    #[test]
+    fn ambiguous_pairs_ignore_case() {
+        let ignore_case = config("ignore-case = true\n");
+        let mut alignment = Alignment::from_rows(AlignmentKind::Global, String::from("ACn"), String::from("ACN"), 0, 0, 0, &ignore_case);
+        alignment.set_aside_ambiguous();
+        assert_eq!((alignment.matches, alignment.mismatches, alignment.ambiguous), (2, 0, 1));
+
+        let mut alignment = Alignment::from_rows(AlignmentKind::Global, String::from("rA-t"), String::from("GaCy"), 0, 0, 0, &ignore_case);
+        alignment.set_aside_ambiguous();
+        assert_eq!((alignment.matches, alignment.mismatches, alignment.ambiguous), (1, 0, 2));
+
+        let mut alignment = Alignment::from_rows(AlignmentKind::Global, String::from("rA-t"), String::from("GaCy"), 0, 0, 0, &config(""));
+        alignment.set_aside_ambiguous();
+        assert_eq!((alignment.matches, alignment.mismatches, alignment.ambiguous), (0, 1, 2));
+    }
+    #[test]
+    fn reverse_strand_coordinates() {
+        let alignment = Alignment::from_rows(AlignmentKind::Local, String::from("AC-GTA"), String::from("ACTG-A"), 3, 1, 0, &config(""));
+        assert_eq!((alignment.s2_start, alignment.s2_end, alignment.strand), (1, 6, Strand::Forward));
+        let reverse = alignment.clone().on_reverse_strand(8);
+        assert_eq!((reverse.s2_start, reverse.s2_end, reverse.strand), (2, 7, Strand::Reverse));
+        assert_eq!((reverse.s1_start, reverse.s1_end, &reverse.s2_row), (alignment.s1_start, alignment.s1_end, &alignment.s2_row));
+
+        let whole = Alignment::from_rows(AlignmentKind::Global, String::from("ACGT"), String::from("ACGT"), 0, 0, 0, &config("")).on_reverse_strand(4);
+        assert_eq!((whole.s2_start, whole.s2_end), (0, 4));
+    }
+    #[test]
+    fn ignore_case_decides_whether_case_scores() {
+        let strict = config("match = 3\nmismatch = -2\n");
+        let ignoring = config("match = 3\nmismatch = -2\nignore-case = true\n");
+        assert_eq!((strict.substitution(b'a', b'A'), strict.substitution(b'a', b'a'), strict.substitution(b'a', b'C')), (-2, 3, -2));
+        assert_eq!((ignoring.substitution(b'a', b'A'), ignoring.substitution(b'G', b'g'), ignoring.substitution(b'a', b'c')), (3, 3, -2));
+    }
+
+    #[test]
+    fn masked_seeds_only_stop_new_alignments() {
+        let masking = config("match = 1\nmismatch = -2\nh = -3\ng = -1\nmask-seeds = true\n");
+        let mut cell = Cell { d_score: 0, i_score: 0, s_score: 1 };
+        cell.mask_seed(&Cell::new(), b'a', b'a', &masking);
+        assert_eq!(cell.s_score, 0);
+        let mut cell = Cell { d_score: 0, i_score: 0, s_score: 2 };
+        cell.mask_seed(&Cell { d_score: 0, i_score: 0, s_score: 1 }, b'a', b'a', &masking);
+        assert_eq!(cell.s_score, 2);
+
+        // a hit that only lowercase pairs could seed is lost, but one seeded in uppercase extends through lowercase
+        let plain = config("match = 1\nmismatch = -2\nh = -3\ng = -1\n");
+        let (s1, s2) = ("GGGGacgtacgtGGGG", "TTTTacgtacgtTTTT");
+        assert_eq!(smith_waterman(s1, s2, &plain).score, 8);
+        assert_eq!(smith_waterman(s1, s2, &masking).score, 0);
+        let alignment = smith_waterman("GGGGACGTacgtGGGG", "TTTTACGTacgtTTTT", &masking);
+        assert_eq!((alignment.score, alignment.s1_row.as_str()), (8, "ACGTacgt"));
+    }
+}
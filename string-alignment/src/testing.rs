@@ -0,0 +1,28 @@
+use crate::config::{ConfigFile, DEFAULT_PROFILE};
+use crate::random::Rng;
+use crate::structs::Config;
+
+
+/// The default profile of a config file given as text
+pub fn config(text: &str) -> Config {
+    ConfigFile::parse(text).unwrap().config(DEFAULT_PROFILE).unwrap()
+}
+
+
+/// A string of exactly len characters drawn uniformly from the alphabet
+pub fn random_string(rng: &mut Rng, alphabet: &[u8], len: usize) -> String {
+    (0..len).map(|_| alphabet[rng.below(alphabet.len())] as char).collect()
+}
+
+
+/// A string of any length from 0 to max_len drawn from the alphabet
+pub fn random_up_to(rng: &mut Rng, alphabet: &[u8], max_len: usize) -> String {
+    let len = rng.below(max_len + 1);
+    random_string(rng, alphabet, len)
+}
+
+
+/// A DNA string of any length from 0 to max_len
+pub fn random_dna(rng: &mut Rng, max_len: usize) -> String {
+    random_up_to(rng, b"ACGT", max_len)
+}
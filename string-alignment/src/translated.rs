@@ -0,0 +1,301 @@
+use crate::algorithms::{best_state, State};
+use crate::codon::{codon_translations, gap_step, state_code, state_of, CodonRows, CodonTrace};
+use crate::dna::{reverse_complement, translate_codon};
+use crate::structs::{Cell, Config, Matrix, Strand, REAL_MIN};
+
+
+/// Marks a codon against a residue that starts the local alignment
+const START: u8 = u8::MAX;
+
+
+/// One column of a translated alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Codon, //a codon against a residue
+    CodonGap, //a codon against a gap in the protein
+    ResidueGap, //a residue against a gap in the DNA
+    Shift(usize), //1 or 2 nucleotides skipped, moving to another frame
+}
+
+
+/// A local alignment of DNA, translated in one of its six frames, against a protein
+#[derive(Debug, Clone)]
+pub struct TranslatedAlignment {
+    pub dna_row: String, //nucleotides, with "---" against residues missing from the DNA
+    pub translation_row: String, //amino acid of each codon under its middle nucleotide, '#' under skipped nucleotides
+    pub ma_row: String, //'|' for identities and '+' for other pairs that score above 0
+    pub protein_row: String, //residues under the middle of their codon, '-' against codons missing from the protein
+    pub strand: Strand,
+    pub frame: i32, //frame the alignment starts in, 1 to 3 on the forward strand and -1 to -3 on the reverse
+    pub dna_start: usize, //0-based, inclusive, counted along the original DNA whichever strand was aligned
+    pub dna_end: usize, //0-based, exclusive
+    pub protein_start: usize,
+    pub protein_end: usize,
+    pub score: i32,
+    pub codons: usize, //codons aligned against residues
+    pub identities: usize,
+    pub positives: usize, //identities included
+    pub gap_opens: usize,
+    pub gapped_codons: usize,
+    pub gapped_residues: usize,
+    pub frameshifts: usize,
+}
+impl TranslatedAlignment {
+
+    /// Number of columns in the alignment, in nucleotides
+    pub fn len(&self) -> usize {
+        self.dna_row.len()
+    }
+
+    /// Whether the alignment has no columns
+    pub fn is_empty(&self) -> bool {
+        self.dna_row.is_empty()
+    }
+}
+
+
+/// Locally aligns DNA against a protein like tblastn, translating both strands of the DNA in every frame.
+/// Codons against residues score like amino acids under the config, a gap of k whole codons or residues costs h + g*k,
+/// and skipping 1 or 2 nucleotides to change frame costs `frameshift`. Returns None if nothing scores above 0.
+pub fn translated_align(dna: &str, protein: &str, config: &Config, frameshift: i32) -> Option<TranslatedAlignment> {
+    let forward = align_strand(dna, protein, config, frameshift);
+    let reversed = reverse_complement(dna);
+    let reverse = align_strand(&reversed, protein, config, frameshift);
+
+    // keep the forward strand unless the reverse one does better
+    let (strand, strand_dna, (score, steps, i_0, j_0)) = match (forward, reverse) {
+        (Some(forward), Some(reverse)) if reverse.0 > forward.0 => (Strand::Reverse, reversed.as_str(), reverse),
+        (Some(forward), _) => (Strand::Forward, dna, forward),
+        (None, Some(reverse)) => (Strand::Reverse, reversed.as_str(), reverse),
+        (None, None) => return None,
+    };
+
+    // lay out the rows and count what happened
+    let mut alignment = TranslatedAlignment {
+        dna_row: String::new(),
+        translation_row: String::new(),
+        ma_row: String::new(),
+        protein_row: String::new(),
+        strand,
+        frame: (i_0 % 3 + 1) as i32,
+        dna_start: i_0,
+        dna_end: i_0,
+        protein_start: j_0,
+        protein_end: j_0,
+        score,
+        codons: 0,
+        identities: 0,
+        positives: 0,
+        gap_opens: 0,
+        gapped_codons: 0,
+        gapped_residues: 0,
+        frameshifts: 0,
+    };
+    let (mut i, mut j) = (i_0, j_0);
+    let mut last_step: Option<Step> = None;
+    for &step in &steps {
+        match step {
+            Step::Codon | Step::CodonGap => {
+                let codon = &strand_dna[i..i+3];
+                let amino_acid = translate_codon(codon.as_bytes()) as char;
+                alignment.dna_row += codon;
+                alignment.translation_row += &format!(" {} ", amino_acid);
+                if step == Step::Codon {
+                    let residue = protein.as_bytes()[j] as char;
+                    alignment.codons += 1;
+                    let ma = if amino_acid.eq_ignore_ascii_case(&residue) {
+                        alignment.identities += 1;
+                        alignment.positives += 1;
+                        '|'
+                    } else if config.substitution(amino_acid as u8, residue as u8) > 0 {
+                        alignment.positives += 1;
+                        '+'
+                    } else {
+                        ' '
+                    };
+                    alignment.ma_row += &format!(" {} ", ma);
+                    alignment.protein_row += &format!(" {} ", residue);
+                    j += 1;
+                } else {
+                    alignment.gapped_codons += 1;
+                    alignment.ma_row += "   ";
+                    alignment.protein_row += " - ";
+                }
+                i += 3;
+            },
+            Step::ResidueGap => {
+                alignment.gapped_residues += 1;
+                alignment.dna_row += "---";
+                alignment.translation_row += "   ";
+                alignment.ma_row += "   ";
+                alignment.protein_row += &format!(" {} ", protein.as_bytes()[j] as char);
+                j += 1;
+            },
+            Step::Shift(skip) => {
+                alignment.frameshifts += 1;
+                alignment.dna_row += &strand_dna[i..i+skip];
+                alignment.translation_row += &"##"[..skip];
+                alignment.ma_row += &"  "[..skip];
+                alignment.protein_row += &"  "[..skip];
+                i += skip;
+            },
+        }
+        if matches!(step, Step::CodonGap | Step::ResidueGap) && last_step != Some(step) {
+            alignment.gap_opens += 1;
+        }
+        last_step = Some(step);
+    }
+    alignment.dna_end = i;
+    alignment.protein_end = j;
+
+    // coordinates on the reverse strand are moved back onto the original DNA
+    if strand == Strand::Reverse {
+        alignment.frame = -alignment.frame;
+        (alignment.dna_start, alignment.dna_end) = (dna.len() - alignment.dna_end, dna.len() - alignment.dna_start);
+    }
+    Some(alignment)
+}
+
+
+/// Best local alignment of one strand of the DNA against the protein, as its score, its steps and where it starts in each string
+fn align_strand(dna: &str, protein: &str, config: &Config, frameshift: i32) -> Option<(i32, Vec<Step>, usize, usize)> {
+    let n = dna.len();
+    let m = protein.len();
+    let codons = codon_translations(dna);
+
+    // the trace's s is START, or 3 * step + previous state, where step 0 is a codon against a residue and 1 or 2 skips that many nucleotides
+    let mut rows = CodonRows::new(m);
+    let mut trace: Matrix<CodonTrace> = Matrix::with_shape(n+1, m+1);
+    let (mut top_score, mut top_i, mut top_j) = (0, 0, 0);
+    for (i, &codon) in codons.iter().enumerate() {
+        for j in 0..m+1 {
+            let mut cell = Cell { d_score: REAL_MIN, i_score: REAL_MIN, s_score: REAL_MIN };
+            let mut step = CodonTrace::default();
+
+            // a codon against a residue, continuing the best alignment that ends just before them or starting a new one
+            if i >= 3 && j >= 1 {
+                let prev = rows.get(i-3, j-1);
+                let pair = config.substitution(codon, protein.as_bytes()[j-1]);
+                if prev.score() > 0 {
+                    cell.s_score = prev.score() + pair;
+                    step.s = state_code(best_state(prev));
+                } else {
+                    cell.s_score = pair;
+                    step.s = START;
+                }
+            }
+
+            // skipping nucleotides only ever continues an alignment
+            for skip in 1..3 {
+                if i >= skip {
+                    let prev = rows.get(i-skip, j);
+                    if prev.score() > 0 && prev.score() + frameshift > cell.s_score {
+                        cell.s_score = prev.score() + frameshift;
+                        step.s = 3 * skip as u8 + state_code(best_state(prev));
+                    }
+                }
+            }
+
+            // a codon against a gap in the protein, opening a new gap unless it extends one
+            if i >= 3 {
+                (cell.d_score, step.d) = gap_step(rows.get(i-3, j), State::D, config.h2, config.g2);
+            }
+
+            // a residue against a gap in the DNA
+            if j >= 1 {
+                (cell.i_score, step.i) = gap_step(rows.get(i, j-1), State::I, config.h1, config.g1);
+            }
+
+            // alignments end on a codon against a residue
+            if cell.s_score > top_score {
+                top_score = cell.s_score;
+                top_i = i;
+                top_j = j;
+            }
+            rows.set(i, j, cell);
+            *trace.index_mut(i, j) = step;
+        }
+    }
+    if top_score == 0 {
+        return None;
+    }
+
+    // follow the trace back to where the alignment started
+    let mut steps: Vec<Step> = Vec::new();
+    let (mut i, mut j) = (top_i, top_j);
+    let mut state = State::S;
+    loop {
+        let step = trace.index(i, j);
+        let prev = match state {
+            State::S if step.s == START || step.s < 3 => {
+                steps.push(Step::Codon);
+                i -= 3;
+                j -= 1;
+                if step.s == START {
+                    break;
+                }
+                step.s
+            },
+            State::S => {
+                let skip = (step.s / 3) as usize;
+                steps.push(Step::Shift(skip));
+                i -= skip;
+                step.s % 3
+            },
+            State::D => {
+                steps.push(Step::CodonGap);
+                i -= 3;
+                step.d
+            },
+            State::I => {
+                steps.push(Step::ResidueGap);
+                j -= 1;
+                step.i
+            },
+        };
+        state = state_of(prev);
+    }
+    steps.reverse();
+    Some((top_score, steps, i, j))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::config;
+
+    fn blosum62() -> Config {
+        config("matrix = BLOSUM62\nh = -10\ng = -1\n")
+    }
+
+    #[test]
+    fn forward_frame_and_coordinates() {
+        // M K W F H H coded from the third nucleotide on
+        let alignment = translated_align("GGATGAAATGGTTTCATCACC", "MKWFHH", &blosum62(), -15).unwrap();
+        assert_eq!((alignment.strand, alignment.frame), (Strand::Forward, 3));
+        assert_eq!((alignment.dna_start, alignment.dna_end, alignment.protein_start, alignment.protein_end), (2, 20, 0, 6));
+        assert_eq!(alignment.dna_row, "ATGAAATGGTTTCATCAC");
+        assert_eq!((alignment.codons, alignment.identities, alignment.frameshifts), (6, 6, 0));
+    }
+
+    #[test]
+    fn reverse_frame_and_coordinates() {
+        let dna = reverse_complement("GGATGAAATGGTTTCATCACC");
+        let alignment = translated_align(&dna, "MKWFHH", &blosum62(), -15).unwrap();
+        assert_eq!((alignment.strand, alignment.frame), (Strand::Reverse, -3));
+        assert_eq!((alignment.dna_start, alignment.dna_end, alignment.protein_start, alignment.protein_end), (1, 19, 0, 6));
+        assert_eq!(alignment.dna_row, "ATGAAATGGTTTCATCAC");
+    }
+
+    #[test]
+    fn frameshift_moves_to_another_frame() {
+        // an extra A after the W codon moves the rest of the coding sequence to the next frame
+        let alignment = translated_align("GGATGAAATGGATTTCATCACC", "MKWFHH", &blosum62(), -5).unwrap();
+        assert_eq!((alignment.strand, alignment.frame), (Strand::Forward, 3));
+        assert_eq!((alignment.dna_start, alignment.dna_end, alignment.protein_start, alignment.protein_end), (2, 21, 0, 6));
+        assert_eq!((alignment.codons, alignment.identities, alignment.frameshifts), (6, 6, 1));
+        assert_eq!(alignment.dna_row, "ATGAAATGGATTTCATCAC");
+        assert_eq!(alignment.translation_row, " M  K  W # F  H  H ");
+    }
+}
@@ -0,0 +1,213 @@
+use std::thread;
+use crate::algorithms::retrace;
+use crate::structs::{Alignment, AlignmentKind, Cell, Config, Matrix, REAL_MIN};
+
+
+/// Side length of the square tiles handed to each thread
+const TILE: usize = 256;
+
+
+/// Needleman-Wunsch with the matrix filled by `threads` threads
+pub fn wavefront_needleman_wunsch(s1: &str, s2: &str, config: &Config, threads: usize) -> Alignment {
+    let matrix = wavefront_fill(s1, s2, config, false, threads);
+    let (s1_str, s2_str, _, _) = retrace(|i, j| matrix.index(i, j), s1, s2, config, s1.len(), s2.len(), false);
+    let score = matrix.index(s1.len(), s2.len()).score();
+    Alignment::from_rows(AlignmentKind::Global, s1_str, s2_str, 0, 0, score, config)
+}
+
+
+/// Smith-Waterman with the matrix filled by `threads` threads
+pub fn wavefront_smith_waterman(s1: &str, s2: &str, config: &Config, threads: usize) -> Alignment {
+    let matrix = wavefront_fill(s1, s2, config, true, threads);
+
+    // the first best cell in row order, just like the serial fill finds it
+    let mut top_i: usize = s1.len();
+    let mut top_j: usize = s2.len();
+    let mut top_score: i32 = 0;
+    for i in 1..s1.len()+1 {
+        for j in 1..s2.len()+1 {
+            if matrix.index(i, j).score() > top_score {
+                top_score = matrix.index(i, j).score();
+                top_i = i;
+                top_j = j;
+            }
+        }
+    }
+
+    let (s1_str, s2_str, i_0, j_0) = retrace(|i, j| matrix.index(i, j), s1, s2, config, top_i, top_j, true);
+    Alignment::from_rows(AlignmentKind::Local, s1_str, s2_str, i_0, j_0, top_score, config)
+}
+
+
+/// Fills the same matrix as the serial global or local fill, a tile at a time.
+/// Tiles on one anti-diagonal only depend on tiles of earlier anti-diagonals, so they're computed side by side
+/// into their own buffers, then copied into the matrix before the next anti-diagonal starts.
+pub fn wavefront_fill(s1: &str, s2: &str, config: &Config, local: bool, threads: usize) -> Matrix<Cell> {
+    let n = s1.len();
+    let m = s2.len();
+    let mut matrix: Matrix<Cell> = Matrix::with_shape(n+1, m+1);
+
+    // setup the edges, which are already 0 for local alignment
+    if !local {
+        for i in 1..n+1 {
+            *matrix.index_mut(i, 0) = Cell { d_score: config.d_open(1) + config.g2 * i as i32, i_score: REAL_MIN, s_score: REAL_MIN };
+        }
+        for j in 1..m+1 {
+            *matrix.index_mut(0, j) = Cell { d_score: REAL_MIN, i_score: config.i_open(0) + config.g1 * j as i32, s_score: REAL_MIN };
+        }
+    }
+
+    let tile_rows = n.div_ceil(TILE);
+    let tile_cols = m.div_ceil(TILE);
+    let threads = threads.max(1);
+    for diagonal in 0..(tile_rows + tile_cols).saturating_sub(1) {
+        let tiles: Vec<(usize, usize)> = (0..tile_rows)
+            .filter(|&row| row <= diagonal && diagonal - row < tile_cols)
+            .map(|row| (row, diagonal - row))
+            .collect();
+
+        // each thread takes every threads-th tile of the anti-diagonal
+        let shared = &matrix;
+        let tiles = &tiles;
+        let filled: Vec<(usize, usize, Vec<Cell>)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads.min(tiles.len())).map(|t| {
+                scope.spawn(move || {
+                    tiles.iter()
+                        .skip(t)
+                        .step_by(threads)
+                        .map(|&(row, col)| (row, col, fill_tile(shared, s1, s2, config, local, row, col)))
+                        .collect::<Vec<_>>()
+                })
+            }).collect();
+            handles.into_iter().flat_map(|handle| handle.join().expect("Wavefront thread panicked")).collect()
+        });
+
+        for (row, col, cells) in filled {
+            let (first_i, last_i, first_j, last_j) = tile_bounds(n, m, row, col);
+            let width = last_j - first_j + 2;
+            for i in first_i..last_i+1 {
+                for j in first_j..last_j+1 {
+                    *matrix.index_mut(i, j) = cells[(i + 1 - first_i) * width + (j + 1 - first_j)].clone();
+                }
+            }
+        }
+    }
+    matrix
+}
+
+
+/// First and last rows and columns of the matrix a tile covers
+fn tile_bounds(n: usize, m: usize, row: usize, col: usize) -> (usize, usize, usize, usize) {
+    (row * TILE + 1, ((row + 1) * TILE).min(n), col * TILE + 1, ((col + 1) * TILE).min(m))
+}
+
+
+/// Computes one tile, copying the row above it and the column to its left from the finished part of the matrix.
+/// The returned cells include that border, so the tile's own cells start at (1, 1).
+fn fill_tile(matrix: &Matrix<Cell>, s1: &str, s2: &str, config: &Config, local: bool, row: usize, col: usize) -> Vec<Cell> {
+    let (first_i, last_i, first_j, last_j) = tile_bounds(s1.len(), s2.len(), row, col);
+    let width = last_j - first_j + 2;
+    let mut cells: Vec<Cell> = vec![Cell::new(); (last_i - first_i + 2) * width];
+    for j in first_j-1..last_j+1 {
+        cells[j + 1 - first_j] = matrix.index(first_i-1, j).clone();
+    }
+    for i in first_i..last_i+1 {
+        cells[(i + 1 - first_i) * width] = matrix.index(i, first_j-1).clone();
+    }
+
+    for i in 1..last_i-first_i+2 {
+        let c1 = s1.as_bytes()[first_i + i - 2];
+        for j in 1..width {
+            let match_score = config.substitution(c1, s2.as_bytes()[first_j + j - 2]);
+            let mut new_cell = Cell::next(&cells[(i-1) * width + j], &cells[i * width + j-1], &cells[(i-1) * width + j-1], match_score, config, first_i + i - 1);
+            if local {
+                new_cell.mask_seed(&cells[(i-1) * width + j-1], c1, s2.as_bytes()[first_j + j - 2], config);
+                new_cell.clamp();
+            }
+            cells[i * width + j] = new_cell;
+        }
+    }
+    cells
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::{needleman_wunsch, smith_waterman};
+    use crate::random::Rng;
+    use crate::testing::{config, random_string};
+
+    #[test]
+    fn matches_serial_fill() {
+        let config = config("match = 2\nmismatch = -3\nh1 = -5\ng1 = -1\nh2 = -4\ng2 = -2\n");
+        let mut rng = Rng::new(5);
+        for (n, m) in [(0, 0), (0, 300), (1, 257), (255, 257), (300, 513), (600, 130)] {
+            let s1 = random_string(&mut rng, b"ACGT", n);
+            let s2 = random_string(&mut rng, b"ACGT", m);
+            let global = needleman_wunsch(&s1, &s2, &config);
+            let local = smith_waterman(&s1, &s2, &config);
+            for threads in [1, 2, 3, 8] {
+                let alignment = wavefront_needleman_wunsch(&s1, &s2, &config, threads);
+                assert_eq!((alignment.score, &alignment.s1_row, &alignment.s2_row), (global.score, &global.s1_row, &global.s2_row),
+                    "global {}x{} on {} threads", n, m, threads);
+                let alignment = wavefront_smith_waterman(&s1, &s2, &config, threads);
+                assert_eq!((alignment.score, &alignment.s1_row, &alignment.s2_row, alignment.s1_start, alignment.s2_start),
+                    (local.score, &local.s1_row, &local.s2_row, local.s1_start, local.s2_start),
+                    "local {}x{} on {} threads", n, m, threads);
+            }
+        }
+    }
+
+    /// The matrix needleman_wunsch or smith_waterman fills, one cell after another
+    fn serial_fill(s1: &str, s2: &str, config: &Config, local: bool) -> Matrix<Cell> {
+        let mut matrix: Matrix<Cell> = Matrix::with_shape(s1.len()+1, s2.len()+1);
+        if !local {
+            for i in 1..s1.len()+1 {
+                *matrix.index_mut(i, 0) = Cell { d_score: config.d_open(1) + config.g2 * i as i32, i_score: REAL_MIN, s_score: REAL_MIN };
+            }
+            for j in 1..s2.len()+1 {
+                *matrix.index_mut(0, j) = Cell { d_score: REAL_MIN, i_score: config.i_open(0) + config.g1 * j as i32, s_score: REAL_MIN };
+            }
+        }
+        for i in 1..s1.len()+1 {
+            for j in 1..s2.len()+1 {
+                let (c1, c2) = (s1.as_bytes()[i-1], s2.as_bytes()[j-1]);
+                let mut new_cell = Cell::next(matrix.index(i-1, j), matrix.index(i, j-1), matrix.index(i-1, j-1), config.substitution(c1, c2), config, i);
+                if local {
+                    new_cell.mask_seed(matrix.index(i-1, j-1), c1, c2, config);
+                    new_cell.clamp();
+                }
+                *matrix.index_mut(i, j) = new_cell;
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn fills_every_cell_like_the_serial_fill() {
+        let mut rng = Rng::new(6);
+        for (n, m) in [(0, 0), (0, 40), (1, 1), (7, 12), (255, 257), (300, 513), (600, 130)] {
+            let s1 = random_string(&mut rng, b"ACGTacgt", n);
+            let s2 = random_string(&mut rng, b"ACGTacgt", m);
+            let plain = config("match = 2\nmismatch = -3\nh1 = -5\ng1 = -1\nh2 = -4\ng2 = -2\n");
+            let masked = config("match = 2\nmismatch = -3\nh = -5\ng = -2\nmask-seeds = true\n");
+            let mut tracked = plain.clone();
+            tracked.gap_track = Some((0..n.max(1)).map(|_| rng.below(5) as f64 / 2.0).collect());
+            for (name, config) in [("plain", &plain), ("mask-seeds", &masked), ("gap track", &tracked)] {
+                for local in [false, true] {
+                    let serial = serial_fill(&s1, &s2, config, local);
+                    for threads in [1, 2, 8] {
+                        let filled = wavefront_fill(&s1, &s2, config, local, threads);
+                        for i in 0..n+1 {
+                            for j in 0..m+1 {
+                                assert_eq!(filled.index(i, j), serial.index(i, j),
+                                    "cell ({}, {}) of {}x{} {} local={} on {} threads", i, j, n, m, name, local, threads);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}